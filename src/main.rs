@@ -8,6 +8,12 @@ use crate::checked::{MathError, MathResult};
 
 // 19.标准库类型
 fn main() {
+    // 带参数运行时进入子命令（cargo run -- <子命令> ...），不带参数时照常跑下面各节的示例
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     //region 19.1.箱子、栈和堆
     println!("\n\n*****19.1.箱子、栈和堆*****");
     // 在Rust中，所有值默认都是栈分配的。
@@ -231,13 +237,8 @@ fn main() {
     // Option<T>，有两个变量：
     //      None：表里失败或缺少值
     //      Some(value)：元组结构体，封装了一个 T 类型的值 value
-    fn checked_division(dividend: i32, divisor: i32) -> Option<i32> {
-        if divisor == 0 {
-            None
-        } else {
-            Some(dividend / divisor)
-        }
-    }
+    // checked_division 挪到了 division 模块里，后面的组合子示例和批处理都要用到它
+    use crate::division::checked_division;
     fn try_division(dividend: i32, divisor: i32) {
         match checked_division(dividend, divisor) {
             None => println!("{} / {} failed!", dividend, divisor),
@@ -246,6 +247,23 @@ fn main() {
     }
     try_division(4, 2);
     try_division(1, 0);
+    // 上面两组数是写死的，批量的数据可以用 divide 子命令从文件或标准输入读入：
+    //      cargo run -- divide pairs.csv
+    //      cat pairs.csv | cargo run -- divide
+    // 每行一对 "被除数,除数"，逐行读、逐行写，所以再大的文件也不会整个读进内存
+    // 每行按 CSV 解析，字段可以加引号；引号里的逗号不会把一个字段拆成两个
+    let input = "dividend,divisor\n4,2\n1,0\n\n# 注释行会被跳过\nseven,1\n9,3\n-2147483648,-1\n5,1,1\n\"12\",\"4\"\n\"1,000\",2\n\"7,2\n";
+    let mut output: Vec<u8> = Vec::new();
+    let summary = crate::batch::run(input.as_bytes(), &mut output).unwrap();
+    print!("{}", String::from_utf8_lossy(&output));
+    println!("{}", summary);
+    assert_eq!((summary.rows, summary.succeeded), (9, 3));
+    assert_eq!((summary.division_by_zero, summary.overflow, summary.malformed), (1, 1, 4));
+    assert_eq!(summary.failed_lines, vec![3, 6, 8, 9, 11, 12]);
+    assert_eq!(crate::batch::divide_row("\"1,000\",2"), Err(crate::batch::RowError::InvalidNumber("1,000".to_string())));
+    // 错误信息里的逗号（"expected 2 fields, found 3"）被转义了，每行都还是 5 列
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.lines().all(|line| crate::csv::split(line).unwrap().len() == 5));
     // 绑定 None 到一个变量需要类型标注
     let none: Option<i32> = None;
     let _equivalent_none = None::<i32>;
@@ -256,6 +274,83 @@ fn main() {
     // println!("{:?} unwraps to {:?}", none, none.unwrap());
     //endregion
 
+    //region 19.4.1.Option组合子
+    println!("\n\n*****=====19.4.1.Option组合子=====*****");
+    // 用 match 处理 Option 很啰嗦，Option 自带了一组组合子(combinator)，可以把多步操作串起来。
+    //      map：Some(x) 变成 Some(f(x))，None 保持 None
+    //      and_then：f 本身返回 Option，用来串联多个可能失败的操作（避免出现 Option<Option<T>>）
+    //      or_else：None 时调用 f 提供一个备选的 Option
+    //      filter：Some(x) 不满足条件时变成 None
+    //      zip：两个都是 Some 时合成 Some((a, b))
+    //      ok_or：转换成 Result，None 变成 Err(err)
+    //      transpose：Option<Result<T, E>> 与 Result<Option<T>, E> 互换
+    use crate::division::{average, checked_chain, parse_and_divide};
+    use crate::option_ext::OptionExt;
+
+    // map：商再乘以 10
+    let scaled = checked_division(12, 4).map(|q| q * 10);
+    println!("checked_division(12, 4).map(|q| q * 10) = {:?}", scaled);
+    assert_eq!(scaled, Some(30));
+    assert_eq!(checked_division(12, 0).map(|q| q * 10), None);
+
+    // and_then：连续做两次除法，任何一步失败整体就是 None
+    let twice = checked_division(100, 5).and_then(|q| checked_division(q, 2));
+    println!("100 / 5 / 2 = {:?}", twice);
+    assert_eq!(twice, Some(10));
+    assert_eq!(checked_division(100, 5).and_then(|q| checked_division(q, 0)), None);
+
+    // or_else：除数为 0 时退而求其次，换一个除数再试
+    let fallback = checked_division(9, 0).or_else(|| checked_division(9, 3));
+    println!("9 / 0 or else 9 / 3 = {:?}", fallback);
+    assert_eq!(fallback, Some(3));
+
+    // filter：只要偶数商
+    assert_eq!(checked_division(8, 2).filter(|q| q % 2 == 0), Some(4));
+    assert_eq!(checked_division(9, 3).filter(|q| q % 2 == 0), None);
+
+    // zip：两个商都算得出来才有结果
+    let both = checked_division(10, 2).zip(checked_division(9, 3));
+    println!("zip of 10 / 2 and 9 / 3 = {:?}", both);
+    assert_eq!(both, Some((5, 3)));
+    assert_eq!(checked_division(10, 2).zip(checked_division(9, 0)), None);
+
+    // ok_or：强调失败原因时转换成 Result
+    let as_result: Result<i32, &str> = checked_division(1, 0).ok_or("division by zero");
+    println!("checked_division(1, 0).ok_or(..) = {:?}", as_result);
+    assert_eq!(as_result, Err("division by zero"));
+
+    // transpose：除数是可选的字符串，先解析再除
+    // Some("4") -> Ok(Some(4))，None -> Ok(None)，Some("x") -> Err(..)
+    let inputs: [Option<&str>; 3] = [Some("4"), None, Some("x")];
+    let parsed: Vec<Result<Option<i32>, _>> = inputs.iter()
+        .map(|input| input.map(str::parse::<i32>).transpose())
+        .collect();
+    println!("{:?} transposed to {:?}", inputs, parsed);
+    assert_eq!(parsed[0], Ok(Some(4)));
+    assert_eq!(parsed[1], Ok(None));
+    assert!(parsed[2].is_err());
+    assert_eq!(parse_and_divide("20", Some("4")), Ok(Some(5)));
+    assert_eq!(parse_and_divide("20", None), Ok(None));
+    assert_eq!(parse_and_divide("20", Some("0")), Ok(None));
+    assert!(parse_and_divide("20", Some("four")).is_err());
+
+    // ? 运算符同样可以用在返回 Option 的函数中：遇到 None 就提前返回 None
+    println!("1000 / 10 / 5 / 2 = {:?}", checked_chain(1000, &[10, 5, 2]));
+    assert_eq!(checked_chain(1000, &[10, 5, 2]), Some(10));
+    assert_eq!(checked_chain(1000, &[10, 0, 2]), None);
+    assert_eq!(average(&[1, 2, 3, 6]), Some(3));
+    assert_eq!(average(&[]), None);
+
+    // OptionExt 是自己给 Option 加的扩展 trait，省得每个项目都重写一遍
+    let quotient = checked_division(7, 0).unwrap_or_log(0, "7 / 0 failed, falling back to 0");
+    assert_eq!(quotient, 0);
+    let quotient = checked_division(7, 7).expect_with(|| format!("{} / {} must succeed", 7, 7));
+    assert_eq!(quotient, 1);
+    let mut missed = 0;
+    let quotient = checked_division(3, 0).on_none(|| missed += 1);
+    assert_eq!((quotient, missed), (None, 1));
+    //endregion
+
     //region 19.5.选项Result
     println!("\n\n*****=====19.5.选项Result=====*****");
     // 我们已经看到 Option 枚举类型可以用途可能失败的函数的返回值 ，其中返回 None 可以表明失败。
@@ -316,15 +411,22 @@ fn main() {
     // 容量的 HashMap，也可以使用 HashMap::new() 来获得一个带有默认初始容量的
     // HashMap。
     use std::collections::HashMap;
-    fn call(number: &str) -> &str {
-        match number {
-            "798-1364" => "We're sorry, the call cannot be completed \
-            Please hang up and try again.",
-            "645-7689" => "Hello, this is Mr. Awesome's Pizza. My name \
-            is Fred. What can I get for you today?",
-            _ => "Hi, Who is this again?"
-        }
-    }
+    // 原来的 call 是对两个号码字面量的 match。现在由 call_router 按配置路由：
+    // 每个号码对应一个处理方式（忙音、语音信箱、问候语、转接到另一个号码），* 是默认路由。
+    // 配置也可以放在文件里：cargo run -- call routes.txt 798-1364
+    use crate::call_router::{CallLog, CallRouter};
+    use crate::clock::SystemClock;
+    const ROUTES: &str = "
+        # 号码 = 处理方式
+        798-1364 = busy
+        645-7689 = greeting Hello, this is Mr. Awesome's Pizza. My name is Fred. What can I get for you today?
+        435-8291 = voicemail Katie
+        956-1745 = forward 645-7689
+        * = greeting Hi, Who is this again?
+    ";
+    let router = CallRouter::from_config(ROUTES).unwrap();
+    let mut call_log = CallLog::new(SystemClock);
+    let mut call = |number: &str| call_log.call(&router, number).outcome.to_string();
     let mut contacts = HashMap::new();
     contacts.insert("Daniel", "798-1364");
     contacts.insert("Ashley", "645-7689");
@@ -349,6 +451,162 @@ fn main() {
     for (contact, &number) in contacts.iter() {
         println!("Calling {}({}): {}", contact, number, call(number));
     }
+    println!("Call log:");
+    for record in call_log.records() {
+        println!("> {}", record);
+    }
+
+    // 转接成环时会被检测出来，而不是无限地转接下去；时钟可以替换成 MockClock，时间戳就是确定的
+    use crate::call_router::CallOutcome;
+    use crate::clock::MockClock;
+    let looping = CallRouter::from_config("111-1111 = forward 222-2222\n222-2222 = forward 111 1111\n* = busy").unwrap();
+    let clock = MockClock::new(1_675_584_000);
+    let mut mock_log = CallLog::new(&clock);
+    let record = mock_log.call(&looping, "111-1111");
+    println!("{}", record);
+    assert_eq!(record.forwarded_via.len(), 2);
+    assert!(matches!(record.outcome, CallOutcome::ForwardingLoop(_)));
+    clock.advance(60);
+    let record = mock_log.call(&router, "956 1745");
+    assert_eq!(record.timestamp, 1_675_584_060);
+    assert!(matches!(record.outcome, CallOutcome::Answered(_)));
+    assert!(matches!(mock_log.call(&router, "435.8291").outcome, CallOutcome::Voicemail(_)));
+    assert!(matches!(mock_log.call(&router, "798-13").outcome, CallOutcome::InvalidNumber(_)));
+    assert_eq!(mock_log.records().len(), 4);
+    assert!(CallRouter::from_config("798-1364 = ring").is_err());
+    assert!(CallRouter::from_config("798-1364 = busy").is_err(), "a default route is required");
+
+    // 上面的 contacts 存的是 &str，每次运行都得重新建一遍。
+    // phone_book::PhoneBook 用自己拥有的 String 存名字和号码，可以保存到文件再加载回来（CSV：name,number）
+    use crate::phone_book::{PhoneBook, PhoneBookError};
+    use crate::phone_number::{PhoneNumber, PhoneNumberError};
+    let number = |s: &str| PhoneNumber::parse(s).unwrap();
+    let book_path = std::env::temp_dir().join("rust_by_example_19_contacts.csv");
+    let mut book = PhoneBook::new();
+    assert!(book.is_empty());
+    for (name, raw) in [("Daniel", "798-1364"), ("Ashley", "645-7689"),
+                        ("Katie", "435-8291"), ("Robert", "956-1745")] {
+        assert_eq!(book.insert(name, number(raw)), None);
+    }
+    // 和 HashMap::insert 一样，替换了已有号码时返回旧号码
+    match book.insert("Daniel", number("164-6743")) {
+        Some(old) => println!("Daniel's number replaced, was {}", old),
+        None => println!("Daniel added"),
+    }
+    // add 不允许覆盖，update 只能修改已有的联系人
+    assert_eq!(book.add("Daniel", number("000-0000")), Err(PhoneBookError::AlreadyExists("Daniel".to_string())));
+    assert_eq!(book.update("Katie", number("435-8292")), Ok(number("435-8291")));
+    assert_eq!(book.update("Nobody", number("000-0000")), Err(PhoneBookError::NotFound("Nobody".to_string())));
+    assert_eq!(book.remove("Ashley"), Ok(number("645-7689")));
+    assert_eq!(book.lookup("Ashley"), None);
+    book.add("O'Brien, Pat", number("+1 (555) 010-0100")).unwrap();
+
+    // 先写临时文件再 rename，写到一半崩溃也不会留下半个通讯录
+    book.save(&book_path).unwrap();
+    let reloaded = PhoneBook::load(&book_path).unwrap();
+    assert_eq!(reloaded, book);
+    println!("Reloaded {} contacts from {}", reloaded.len(), book_path.display());
+    for (name, number) in reloaded.list() {
+        println!("> {}: {}", name, number);
+    }
+    assert_eq!(reloaded.lookup("O'Brien, Pat"), Some(&number("555-010-0100")));
+    // 名字里有换行时，带引号的字段跨两行写出，加载时也按一条记录读回来
+    let mut multiline = PhoneBook::new();
+    multiline.add("Pat\n(night line)", number("555-2368")).unwrap();
+    multiline.add("Katie", number("435-8291")).unwrap();
+    multiline.save(&book_path).unwrap();
+    assert_eq!(PhoneBook::load(&book_path).unwrap(), multiline);
+    std::fs::write(&book_path, "Katie,435-8291\n\"Pat\n(night line),555-2368\n").unwrap();
+    assert!(matches!(PhoneBook::load(&book_path), Err(PhoneBookError::Parse { line: 2, .. })));
+    std::fs::remove_file(&book_path).unwrap();
+
+    // 号码不再是不透明的字符串：PhoneNumber::parse 认识常见的几种写法，并规范化成同一个值
+    for raw in ["798-1364", "798 1364", "(555) 798-1364", "555.798.1364", "+1 555 798 1364",
+                "1-555-798-1364", "+44 20 7946 0958"] {
+        let parsed = number(raw);
+        println!("{:>18} => {} (country code {}, area code {:?})",
+                 raw, parsed, parsed.country_code(), parsed.area_code());
+    }
+    assert_eq!(number("798 1364"), number("798-1364"));
+    assert_eq!(number("+1 555 798 1364"), number("(555) 798-1364"));
+    assert_eq!(number("555.798.1364").digits(), "5557981364");
+    assert_eq!(PhoneNumber::parse(""), Err(PhoneNumberError::Empty));
+    assert_eq!(PhoneNumber::parse("798-13a4"), Err(PhoneNumberError::InvalidCharacter('a')));
+    assert_eq!(PhoneNumber::parse("(555 798-1364"), Err(PhoneNumberError::UnbalancedParentheses));
+    assert_eq!(PhoneNumber::parse("555+7981364"), Err(PhoneNumberError::MisplacedPlus));
+    assert_eq!(PhoneNumber::parse("+1234 5678"), Err(PhoneNumberError::InvalidCountryCode));
+    assert_eq!(PhoneNumber::parse("798-136"), Err(PhoneNumberError::InvalidLength(6)));
+    // 无论用户怎么输入号码，都能在通讯录里查到
+    assert_eq!(book.find_by_number(&number("164.6743")), Some("Daniel"));
+    assert_eq!(book.find_by_number(&number("+1 (555) 010 0100")), Some("O'Brien, Pat"));
+    assert_eq!(book.find_by_number(&number("111-1111")), None);
+
+    // contacts.get(&"Daniel") 要求名字完全一致（包括大小写）。
+    // contact_search::search 支持忽略大小写、前缀、编辑距离模糊匹配和按部分号码查找，结果按相关度排序。
+    // 交互式查找：cargo run -- lookup contacts.csv
+    use crate::contact_search::{levenshtein, search, MatchKind};
+    book.add("Danielle", number("555-2368")).unwrap();
+    for query in ["daniel", "dan", "Danial", "6743", "nobody"] {
+        let hits: Vec<String> = search(&book, query).iter()
+            .map(|hit| format!("{} ({}, {:?})", hit.name, hit.number, hit.kind))
+            .collect();
+        println!("Search {:?}: {:?}", query, hits);
+    }
+    assert_eq!(levenshtein("danial", "daniel"), 1);
+    let hits = search(&book, "DANIEL");
+    assert_eq!((hits[0].name, hits[0].kind), ("Daniel", MatchKind::Exact));
+    assert_eq!((hits[1].name, hits[1].kind), ("Danielle", MatchKind::Prefix));
+    let hits = search(&book, "Danial");
+    assert_eq!((hits[0].name, hits[0].kind), ("Daniel", MatchKind::Fuzzy(1)));
+    let hits = search(&book, "164-67");
+    assert_eq!((hits[0].name, hits[0].kind), ("Daniel", MatchKind::Number));
+    assert!(search(&book, "nobody").is_empty());
+
+    // HashMap::iter 的顺序是任意的，每次运行都可能不同，输出没法 diff。
+    // PhoneBook 在 HashMap 旁边维护了有序索引：list 按插入顺序，另外还能按名字或号码排序，按名字查找仍是 O(1)
+    let names: Vec<&str> = book.list().map(|(name, _)| name).collect();
+    println!("Insertion order: {:?}", names);
+    assert_eq!(names, ["Daniel", "Katie", "Robert", "O'Brien, Pat", "Danielle"]);
+    let names: Vec<&str> = book.sorted_by_name().map(|(name, _)| name).collect();
+    println!("Sorted by name: {:?}", names);
+    assert_eq!(names, ["Daniel", "Danielle", "Katie", "O'Brien, Pat", "Robert"]);
+    let numbers: Vec<String> = book.sorted_by_number().map(|(_, number)| number.to_string()).collect();
+    println!("Sorted by number: {:?}", numbers);
+    // 号码按数字串的字典序比较，带区号的 (555) 010-0100 即 5550100100 排在 5552368 前面
+    assert_eq!(numbers, ["164-6743", "435-8292", "(555) 010-0100", "555-2368", "956-1745"]);
+    // 替换号码不改变插入顺序，删除后再加入则排到最后
+    book.insert("Daniel", number("798-1364"));
+    book.remove("Katie").unwrap();
+    book.add("Katie", number("435-8291")).unwrap();
+    let names: Vec<&str> = book.list().map(|(name, _)| name).collect();
+    assert_eq!(names, ["Daniel", "Robert", "O'Brien, Pat", "Danielle", "Katie"]);
+    assert_eq!(book.find_by_number(&number("798 1364")), Some("Daniel"));
+    assert_eq!(book.find_by_number(&number("164-6743")), None);
+
+    // 上面只用到了 insert、get 和 remove。统计和分组用 entry API 更方便，只查一次表：
+    // grouping 模块把常见的几种用法包装成了函数
+    use crate::grouping::{count_by, fold_by, group_by, top_k};
+    use std::collections::BTreeMap;
+    // HashMap 的遍历顺序不固定，打印前放进 BTreeMap 排一下序；具体的检查在 grouping 模块的测试里
+    let words: Vec<&str> = pangram.split_whitespace().collect();
+    println!("Words by length: {:?}", count_by(&words, |word| word.len()).into_iter().collect::<BTreeMap<_, _>>());
+    println!("Words by initial: {:?}", group_by(words.iter().copied(), |word| word.chars().next()).into_iter()
+        .filter_map(|(initial, words)| Some((initial?, words))).collect::<BTreeMap<_, _>>());
+    // 每个首字母下的单词总长度
+    let letters_by_initial = fold_by(&words, |word| word.chars().next(), || 0, |total, word| *total += word.len());
+    println!("Letters by initial: {:?}", letters_by_initial.into_iter()
+        .filter_map(|(initial, total)| Some((initial?, total))).collect::<BTreeMap<_, _>>());
+    // 出现最多的字母，同样多时按字母排
+    let letter_counts = count_by(pangram.chars().filter(|c| c.is_alphabetic()), |&c| c);
+    let top: Vec<(char, usize)> = top_k(&letter_counts, 4).into_iter().map(|(&c, &n)| (c, n)).collect();
+    println!("Most common letters: {:?}", top);
+    // 通讯录：按区号分组、按号码长度计数
+    let by_area = group_by(book.sorted_by_name(), |(_, number)| number.area_code().map(str::to_string));
+    println!("Contacts by area code: {:?}", by_area.iter()
+        .map(|(area, entries)| (area.as_deref().unwrap_or("local"), entries.iter().map(|(name, _)| *name).collect::<Vec<_>>()))
+        .collect::<BTreeMap<_, _>>());
+    println!("Contacts by number of digits: {:?}",
+             count_by(book.list(), |(_, number)| number.digits().len()).into_iter().collect::<BTreeMap<_, _>>());
     //endregion
 
     //region 19.7.1.更改或自定义关键字类型
@@ -360,6 +618,7 @@ fn main() {
     //      的 .get() 方法的参数，以获取值。
     // 注意：
     // f32 和 f64 没有实现 Hash，这很大程度上是由于若使用浮点数作为散列表的键，浮点精度误差会容易导致错误
+    // （确实需要用浮点数作键时，见 19.7.8 的 ordered_float）
     // 对所有的集合类(collection class)，如果它们包含的类型都分别实现了 Eq 和 Hash,那么这些
     // 集合类也就实现了 Eq 和 Hash。
     // 比如：若 T 实现了 Hash，则 Vec<T> 也实现了 Hash。
@@ -367,48 +626,262 @@ fn main() {
     // 来试一个非常简易的用户登录系统
     // 前面已经定义过了！！！ 2023年2月5日16时18分35秒
     // use std::collections::HashMap;
-    #[derive(PartialEq, Eq, Hash)]
-    struct Account<'a> {
-        username: &'a str,
-        password: &'a str,
-    }
-    struct AccountInfo<'a> {
-        name: &'a str,
-        email: &'a str,
-    }
-    type Accounts<'a> = HashMap<Account<'a>, AccountInfo<'a>>;
-    fn try_logon<'a>(accounts: &Accounts<'a>, username: &'a str, password: &'a str) {
-        println!("Username: {}", username);
-        println!("Password: {}", password);
-        println!("Attempting to login...");
-
-        let logon = Account {
-            username: username,
-            password: password,
-        };
-        match accounts.get(&logon) {
-            Some(account_info) => {
+    // 原来的 Account 把 username 和明文 password 一起当作键，口令会被散列表的哈希函数处理、
+    // 再用 == 明文比较。现在键里只有用户名，口令只保存加盐的 scrypt 散列（见 credential 模块），
+    // 验证时用常量时间比较，明文放在 Secret 里，用完即清零。
+    // Account、AccountInfo 和 Accounts 挪到了 accounts 模块，AccountStore 在 Accounts 外面提供
+    // 注册、改口令、改资料、删除和列出账号的接口，出错时返回 AccountError 而不是打印。
+    use crate::accounts::{AccountError, AccountInfo, AccountStore};
+    use crate::credential::{KdfParams, PasswordHash, Secret};
+    // 登录失败会被 LoginThrottle 记下来：同一个用户名连续失败时等待时间指数增长，
+    // 失败次数达到上限就临时锁定；所有用户名加起来失败太多时整体限流
+    use crate::clock::Clock;
+    use crate::login::{LoginError, LoginThrottle, ThrottlePolicy};
+    // 登录成功后签发一个会话令牌，之后凭令牌访问，不用每次都带口令
+    use crate::session::{Session, SessionPolicy, SessionStore};
+    // login::try_logon 不再打印（更不会打印口令），而是返回 Result<&AccountInfo, LoginError>，
+    // 每次尝试都以结构化的审计事件写到一个可替换的 AuditSink 里
+    use crate::audit::{AuditSink, JsonLinesSink, MemorySink};
+    fn logon<C: Clock>(accounts: &AccountStore, throttle: &mut LoginThrottle<C>, sessions: &mut SessionStore<C>,
+                       audit: &mut dyn AuditSink, username: &str, password: &str) -> Option<Session> {
+        println!("Attempting to login as {}...", username);
+        match crate::login::try_logon(accounts, throttle, audit, username, &Secret::from(password)) {
+            Ok(info) => {
                 println!("Successfully logon!");
-                println!("\tName: {}", account_info.name);
-                println!("\tEmail: {}",account_info.email);
+                println!("\tName: {}", info.name);
+                println!("\tEmail: {}", info.email);
+                Some(sessions.issue(username))
+            },
+            Err(LoginError::InvalidCredentials) => {
+                println!("Login failed!");
+                None
+            },
+            Err(why) => {
+                println!("Login failed: {}", why);
+                None
             },
-            _ => println!("Login failed!"),
         }
     }
 
-    let mut accounts: Accounts = HashMap::new();
-    let account = Account {
-        username: "j.everyman",
-        password: "password123",
+    // 示例用较低的代价参数，真正使用时用 AccountStore::new()
+    let mut accounts = AccountStore::with_params(KdfParams::FAST);
+    let account_info = AccountInfo::new("John Everyman", "j.everyman@gmail.com");
+    accounts.register("j.everyman", Secret::from("password123"), account_info).unwrap();
+    let mut throttle = LoginThrottle::new(SystemClock, ThrottlePolicy::default());
+    let mut sessions = SessionStore::new(SystemClock, SessionPolicy::default());
+    let audit_path = std::env::temp_dir().join("rust_by_example_19_audit.jsonl");
+    // JsonLinesSink 是追加写的，先删掉上次运行留下的文件
+    let _ = std::fs::remove_file(&audit_path);
+    let mut audit = JsonLinesSink::create(&audit_path).unwrap();
+    let session = logon(&accounts, &mut throttle, &mut sessions, &mut audit, "j.everyman", "password123");
+    println!("Session: {:?}", session);
+    logon(&accounts, &mut throttle, &mut sessions, &mut audit, "j.everyman", "password23");
+    // 紧接着再试一次，还在退避时间内，连口令都不会去核对
+    logon(&accounts, &mut throttle, &mut sessions, &mut audit, "j.everyman", "password123");
+    drop(audit);
+    let audit_log = std::fs::read_to_string(&audit_path).unwrap();
+    println!("Audit log:");
+    print!("{}", audit_log);
+    assert_eq!(audit_log.lines().count(), 3);
+    assert!(!audit_log.contains("password"));
+    std::fs::remove_file(&audit_path).unwrap();
+    let token = session.unwrap().token;
+    println!("Token belongs to {:?}", sessions.validate(token.as_str()));
+
+    // 会话的有效期：签发后最多 8 小时，闲置 30 分钟失效；刷新会换新令牌，旧令牌立即作废，
+    // 但一直刷新也不能超过登录后 7 天。各种情况的检查在 session 模块的测试里
+    use crate::clock::format_timestamp;
+    let clock = MockClock::new(1_675_584_000);
+    let mut sessions = SessionStore::new(&clock, SessionPolicy::default());
+    let session = sessions.issue("j.everyman");
+    println!("Issued to {} at {}, expires at {}", session.username, format_timestamp(clock.now()), format_timestamp(session.expires_at));
+    clock.advance(20 * 60);
+    let refreshed = sessions.refresh(session.token.as_str()).unwrap();
+    println!("Refreshed at {}, expires at {}; old token: {:?}",
+             format_timestamp(clock.now()), format_timestamp(refreshed.expires_at), sessions.validate(session.token.as_str()));
+    clock.advance(31 * 60);
+    println!("Idle for 31 minutes: {:?}", sessions.validate(refreshed.token.as_str()));
+    // 吊销
+    let first = sessions.issue("j.everyman");
+    sessions.issue("J.Everyman");
+    sessions.issue("jane");
+    println!("{} sessions, revoked one: {}, revoked the rest of j.everyman's: {}",
+             sessions.len(), sessions.revoke(first.token.as_str()), sessions.revoke_all("j.everyman"));
+    clock.advance(31 * 60);
+    println!("Purged {} idle session(s), none left: {}", sessions.purge_expired(), sessions.is_empty());
+
+    // 用 MockClock 演示退避和锁定：默认策略下第 n 次失败后要等 2^(n-1) 秒，失败 5 次锁定 15 分钟。
+    // 每次都等到提示的时间再试，第 5 次失败后被锁定。各种情况的检查在 login 模块的测试里
+    let clock = MockClock::new(1_675_584_000);
+    let mut throttle = LoginThrottle::new(&clock, ThrottlePolicy::default());
+    let mut audit = MemorySink::default();
+    let start = clock.now();
+    for _ in 0..10 {
+        let result = crate::login::try_logon(&accounts, &mut throttle, &mut audit, "j.everyman", &Secret::from("wrong"));
+        match result {
+            Err(LoginError::Throttled { retry_after } | LoginError::LockedOut { retry_after }) => {
+                println!("after {:>3}s: {}", clock.now() - start, result.unwrap_err());
+                clock.advance(retry_after);
+            }
+            _ => println!("after {:>3}s: {:?}", clock.now() - start, result.map(|info| &info.name)),
+        }
+    }
+    println!("{} failures recorded for {} username(s)", throttle.failures("j.everyman"), throttle.tracked_users());
+    // 审计事件里有时间、用户名、结果和原因
+    println!("{}", audit.events.last().unwrap().to_json());
+
+    // 每个操作都返回 Result，管理工具可以根据错误类型处理
+    let jane = || AccountInfo::new("Jane Doe", "jane@example.com");
+    assert_eq!(accounts.register("jane", Secret::from("correct horse"), jane()), Ok(()));
+    assert_eq!(accounts.register("JANE", Secret::from("battery staple"), jane()), Err(AccountError::UsernameTaken));
+    assert_eq!(accounts.register("jdoe", Secret::from("battery staple"), jane()), Err(AccountError::EmailTaken));
+    assert!(matches!(accounts.register("j", Secret::from("battery staple"), jane()), Err(AccountError::InvalidUsername(_))));
+    assert!(matches!(accounts.register("jane doe", Secret::from("battery staple"), jane()), Err(AccountError::InvalidUsername(_))));
+    assert_eq!(accounts.register("jdoe", Secret::from("battery staple"), AccountInfo::new("J", "jd@localhost")),
+               Err(AccountError::InvalidEmail));
+    assert_eq!(accounts.register("jdoe", Secret::from("short"), AccountInfo::new("J", "jd@example.com")),
+               Err(AccountError::WeakPassword));
+
+    assert_eq!(accounts.change_password("jane", &Secret::from("wrong"), Secret::from("new password")),
+               Err(AccountError::WrongPassword));
+    // 改了口令，之前登录的会话都要作废
+    use crate::account_lifecycle;
+    let before = sessions.issue("jane");
+    let revoked = account_lifecycle::change_password(&mut accounts, &mut sessions, "Jane",
+                                                     &Secret::from("correct horse"), Secret::from("new password")).unwrap();
+    println!("Password changed, {} session(s) revoked, old token: {:?}", revoked, sessions.validate(before.token.as_str()));
+    assert!(accounts.verify_password("jane", &Secret::from("new password")).is_ok());
+    // 用户名不存在时也会核对一次（随机口令的）散列，耗时和口令错误差不多
+    assert_eq!(accounts.verify_password("nobody", &Secret::from("new password")).map(|_| ()), Err(AccountError::NotFound));
+    accounts.update_info("jane", AccountInfo::new("Jane Roe", "jane.roe@example.com")).unwrap();
+    assert_eq!(accounts.get("JANE").map(|info| info.email.as_str()), Some("jane.roe@example.com"));
+    assert_eq!(accounts.update_info("jane", AccountInfo::new("Jane", "j.everyman@gmail.com")), Err(AccountError::EmailTaken));
+    assert_eq!(accounts.update_info("nobody", jane()), Err(AccountError::NotFound));
+    for (username, info) in accounts.list() {
+        println!("> {}: {} <{}>", username, info.name, info.email);
+    }
+
+    // 程序退出后账号不应该消失：accounts_db 把 AccountStore 保存成带版本号和校验和的文件。
+    // 写入时先写临时文件再 rename；加载时校验和对不上就报 Corrupted；旧版本的文件会逐级迁移到当前版本
+    use crate::accounts_db::{self, DbError};
+    let db_path = std::env::temp_dir().join("rust_by_example_19_accounts.db");
+    accounts.register("katie", Secret::from("hunter2hunter2"),
+                      AccountInfo::new("Katie", "katie@example.com").with_phone(number("435-8291"))).unwrap();
+    accounts_db::save(&accounts, &db_path).unwrap();
+    let reloaded = accounts_db::load(&db_path, KdfParams::FAST).unwrap();
+    assert_eq!(reloaded.list(), accounts.list());
+    assert!(reloaded.verify_password("katie", &Secret::from("hunter2hunter2")).is_ok());
+    let contents = std::fs::read_to_string(&db_path).unwrap();
+    println!("{}", contents.lines().take(2).collect::<Vec<_>>().join("\n"));
+
+    // 改动一个字节，校验和就对不上了
+    std::fs::write(&db_path, contents.replace("Katie", "Kathy")).unwrap();
+    assert!(matches!(accounts_db::load(&db_path, KdfParams::FAST), Err(DbError::Corrupted(_))));
+    std::fs::write(&db_path, contents.replacen("accounts-db 2", "accounts-db 9", 1)).unwrap();
+    assert!(matches!(accounts_db::load(&db_path, KdfParams::FAST), Err(DbError::UnsupportedVersion(9))));
+
+    // 第 1 版的文件没有 phone 字段，加载时自动迁移
+    let v1_body = format!("j.everyman\t{}\tJohn Everyman\tj.everyman@gmail.com\n",
+                          PasswordHash::new(&Secret::from("password123"), KdfParams::FAST));
+    std::fs::write(&db_path, accounts_db::encode(1, &v1_body)).unwrap();
+    let migrated = accounts_db::load(&db_path, KdfParams::FAST).unwrap();
+    assert_eq!(migrated.get("j.everyman"), Some(&AccountInfo::new("John Everyman", "j.everyman@gmail.com")));
+    assert!(migrated.verify_password("j.everyman", &Secret::from("password123")).is_ok());
+    std::fs::remove_file(&db_path).unwrap();
+
+    // AccountInfo 只说明“是谁”，permissions::Policy 决定“能做什么”：
+    // 角色带有权限并可以继承其他角色，账号被分配角色，用 can(用户, 动作, 资源) 检查。
+    // 继承、撤销和各种错误情况在 permissions 模块的测试里
+    use crate::permissions::{Action, Permission, Policy};
+    let mut policy = Policy::new();
+    policy.define_role("viewer", &[]).unwrap();
+    policy.define_role("editor", &["viewer"]).unwrap();
+    policy.define_role("admin", &[]).unwrap();
+    policy.inherit("admin", "editor").unwrap();
+    policy.grant("viewer", Permission::new(Action::Read, "contacts/*")).unwrap();
+    policy.grant("editor", Permission::new(Action::Write, "contacts/*")).unwrap();
+    policy.grant("admin", Permission::new(Action::Delete, "*")).unwrap();
+    policy.grant("admin", Permission::new(Action::Admin, "accounts/*")).unwrap();
+
+    let show = |policy: &Policy, username: &str| {
+        println!("{}: roles {:?}, read contacts/daniel: {}, delete contacts/daniel: {}, admin accounts/jane: {}",
+                 username, policy.effective_roles(username).into_iter().collect::<Vec<_>>(),
+                 policy.can(username, Action::Read, "contacts/daniel"),
+                 policy.can(username, Action::Delete, "contacts/daniel"),
+                 policy.can(username, Action::Admin, "accounts/jane"));
     };
+    policy.assign(&accounts, "J.Everyman", "editor").unwrap();
+    show(&policy, "j.everyman");
+    policy.assign(&accounts, "j.everyman", "admin").unwrap();
+    show(&policy, "j.everyman");
+    policy.unassign("j.everyman", "admin");
+    policy.revoke("viewer", &Permission::new(Action::Read, "contacts/*")).unwrap();
+    policy.grant("viewer", Permission::new(Action::Read, "contacts/daniel")).unwrap();
+    show(&policy, "j.everyman");
+    println!("Assigning a role to mallory: {:?}", policy.assign(&accounts, "mallory", "admin").unwrap_err());
+    policy.forget_user("j.everyman");
+    show(&policy, "j.everyman");
+
+    // 只有口令还不够：账号可以选择启用基于时间的一次性密码（TOTP，RFC 6238）作为第二步验证。
+    // RFC 6238 的测试向量、配置检查和验证码重放在 totp 模块的测试里，登录流程在 login 模块的测试里
+    use crate::totp::{base32_decode, base32_encode, totp, Algorithm, TotpConfig, TotpStore};
+    let config = TotpConfig::new(Algorithm::Sha1, 6, 30).and_then(|config| config.with_skew(1)).unwrap();
+    println!("TOTP: {} digits every {} seconds, \"foobar\" in base32: {}", config.digits(), config.period(), base32_encode(b"foobar"));
+    // 同一个时间点，三种 HMAC 算法算出的 8 位验证码（RFC 6238 附录 B 的第一行）
+    for (algorithm, seed) in [(Algorithm::Sha1, &b"12345678901234567890"[..]),
+                              (Algorithm::Sha256, &b"12345678901234567890123456789012"[..]),
+                              (Algorithm::Sha512, &b"1234567890123456789012345678901234567890123456789012345678901234"[..])] {
+        println!("{:?} at 59: {}", algorithm, totp(seed, 59, &TotpConfig::new(algorithm, 8, 30).unwrap()));
+    }
+
+    // 绑定：生成密钥、otpauth URI 和恢复码，用户输入第一个验证码确认后才启用
+    let clock = MockClock::new(1_675_584_000);
+    let mut two_factor = TotpStore::new(&clock, "RustByExample", config);
+    let provisioning = two_factor.enroll("j.everyman").unwrap();
+    println!("Provisioning URI: {}", provisioning.uri);
+    println!("Recovery codes: {:?}", provisioning.recovery_codes);
+    let key = base32_decode(&provisioning.secret_base32).unwrap();
+    two_factor.confirm("j.everyman", &totp(&key, clock.now(), &config)).unwrap();
+    println!("Two-factor enabled: {}", two_factor.is_enabled("j.everyman"));
 
-    let account_info = AccountInfo {
-        name: "John Everyman",
-        email: "j.everyman@gmail.com",
+    // 登录：口令对了还要验证码
+    let mut throttle = LoginThrottle::new(&clock, ThrottlePolicy::default());
+    let mut audit = MemorySink::default();
+    let mut logon_2fa = |two_factor: &mut TotpStore<_>, code: Option<&str>| {
+        crate::login::try_logon_with_totp(&accounts, &mut throttle, &mut audit, two_factor, "j.everyman",
+                                          &Secret::from("password123"), code).map(|info| info.name.clone())
     };
-    accounts.insert(account, account_info);
-    try_logon(&accounts, "j.everyman", "password123");
-    try_logon(&accounts, "j.everyman", "password23");
+    println!("Without a code: {:?}", logon_2fa(&mut two_factor, None));
+    clock.advance(60);
+    println!("With a code: {:?}", logon_2fa(&mut two_factor, Some(&totp(&key, clock.now(), &config))));
+    // 手机丢了可以用恢复码，每个只能用一次
+    println!("With a recovery code: {:?}", logon_2fa(&mut two_factor, Some(&provisioning.recovery_codes[0])));
+    println!("{} recovery code(s) left", two_factor.recovery_codes_left("j.everyman"));
+    println!("Disabled: {}, verify afterwards: {:?}", two_factor.disable("j.everyman"), two_factor.verify("j.everyman", "123456"));
+
+    // 删除账号时会话、角色和两步验证一起清掉，之后用同一个用户名注册的人什么也继承不到。
+    // 具体的检查在 account_lifecycle 模块的测试里
+    policy.assign(&accounts, "katie", "admin").unwrap();
+    let provisioning = two_factor.enroll("katie").unwrap();
+    let key = base32_decode(&provisioning.secret_base32).unwrap();
+    two_factor.confirm("katie", &totp(&key, clock.now(), &config)).unwrap();
+    let session = sessions.issue("katie");
+    let deleted = account_lifecycle::delete(&mut accounts, &mut sessions, &mut policy, &mut two_factor, "Katie").unwrap();
+    println!("Deleted {}, old session: {:?}", deleted.name, sessions.validate(session.token.as_str()));
+    accounts.register("katie", Secret::from("not the same katie"), AccountInfo::new("Katie", "katie@example.org")).unwrap();
+    println!("New katie: roles {:?}, two-factor enabled: {}",
+             policy.effective_roles("katie"), two_factor.is_enabled("katie"));
+    accounts.delete("katie").unwrap();
+    println!("{} account(s) left, empty: {}", accounts.len(), accounts.is_empty());
+
+    // 同一个口令，每次的盐不同，散列也不同；散列字符串里记着参数和盐，可以解析回来。
+    // 与标准测试向量的对照、篡改参数的检查在 credential 模块的测试里
+    let first = PasswordHash::new(&Secret::from("password123"), KdfParams::FAST);
+    let second = PasswordHash::new(&Secret::from("password123"), KdfParams::FAST);
+    println!("{}\n{}", first, second);
+    println!("Parsed back: {}, verifies: {}", first.to_string().parse::<PasswordHash>() == Ok(first.clone()),
+             first.verify(&Secret::from("password123")));
+    println!("{:?}", Secret::from("password123"));
     //endregion
 
     //region 19.7.2.散列集HashSet
@@ -441,6 +914,357 @@ fn main() {
     println!("Difference: {:?}", a.difference(&b).collect::<Vec<&i32>>());
     println!("Intersection: {:?}", a.intersection(&b).collect::<Vec<&i32>>());
     println!("Symmetric Difference: {:?}", a.symmetric_difference(&b).collect::<Vec<&i32>>());
+
+    // 上面的四种运算都只针对两个集合。set_algebra 把它们推广到任意多个集合，
+    // 另外还有集合之间的关系、幂集和笛卡尔积，以及一个小小的集合表达式语言
+    use crate::set_algebra::{self, cartesian_product, power_set, Relation};
+    let c: HashSet<i32> = [4, 5, 6].into_iter().collect();
+    let sorted = |set: HashSet<i32>| { let mut v: Vec<i32> = set.into_iter().collect(); v.sort(); v };
+    assert_eq!(sorted(set_algebra::union_all([&a, &b, &c])), [1, 2, 3, 4, 5, 6]);
+    assert_eq!(set_algebra::intersection_all([&a, &b, &c]).map(sorted), Some(vec![4]));
+    assert_eq!(set_algebra::intersection_all(Vec::<&HashSet<i32>>::new()), None);
+    assert_eq!(sorted(set_algebra::difference_all(&a, [&b, &c])), [1]);
+    // 对称差：出现在奇数个集合中的元素，4 出现在三个集合里
+    assert_eq!(sorted(set_algebra::symmetric_difference_all([&a, &b, &c])), [1, 4, 6]);
+    assert!(!set_algebra::pairwise_disjoint([&a, &b, &c]));
+    let evens: HashSet<i32> = [2, 4].into_iter().collect();
+    let odds: HashSet<i32> = [1, 3].into_iter().collect();
+    assert!(set_algebra::pairwise_disjoint([&evens, &odds]));
+
+    assert_eq!(set_algebra::relation(&evens, &a), Relation::Subset);
+    assert_eq!(set_algebra::relation(&a, &evens), Relation::Superset);
+    assert_eq!(set_algebra::relation(&a, &a.clone()), Relation::Equal);
+    assert_eq!(set_algebra::relation(&evens, &odds), Relation::Disjoint);
+    assert_eq!(set_algebra::relation(&a, &b), Relation::Overlapping);
+
+    // 幂集有 2^n 个子集，笛卡尔积有 |A| * |B| 个元组
+    let subsets: Vec<Vec<&i32>> = power_set(&odds).collect();
+    println!("Power set of {:?}: {:?}", odds, subsets);
+    assert_eq!(power_set(&a).count(), 16);
+    assert_eq!(power_set(&HashSet::<i32>::new()).count(), 1);
+    let pairs: Vec<Vec<&i32>> = cartesian_product([&evens, &odds]).collect();
+    println!("{:?} x {:?}: {:?}", evens, odds, pairs);
+    assert_eq!(pairs.len(), 4);
+    assert_eq!(cartesian_product([&a, &b, &c]).count(), 4 * 4 * 3);
+    assert_eq!(cartesian_product([&a, &HashSet::new()]).count(), 0);
+
+    // 集合表达式：| 并集，& 交集，- 差集，^ 对称差，可以用括号，优先级同 Python
+    // 命名集合也可以从文件加载：cargo run -- sets sets.txt "(A | B) - C"
+    use crate::set_algebra::ExprError;
+    let named: HashMap<String, HashSet<i32>> = [("A", &a), ("B", &b), ("C", &c)].into_iter()
+        .map(|(name, set)| (name.to_string(), set.clone()))
+        .collect();
+    for expr in ["(A | B) - C", "A | B - C", "A & B ^ C", "A - (B - C)"] {
+        println!("{} = {:?}", expr, sorted(set_algebra::evaluate(expr, &named).unwrap()));
+    }
+    assert_eq!(sorted(set_algebra::evaluate("(A | B) - C", &named).unwrap()), [1, 2, 3]);
+    assert_eq!(sorted(set_algebra::evaluate("A | B - C", &named).unwrap()), [1, 2, 3, 4]);
+    assert_eq!(set_algebra::evaluate("A | D", &named), Err(ExprError::UnknownSet("D".to_string())));
+    assert_eq!(set_algebra::evaluate("A | ", &named), Err(ExprError::UnexpectedEnd));
+    assert_eq!(set_algebra::evaluate("(A | B", &named), Err(ExprError::UnexpectedEnd));
+    assert_eq!(set_algebra::evaluate("A + B", &named), Err(ExprError::Unexpected { position: 2, found: "+".to_string() }));
+    let fruits = set_algebra::parse_sets("fruit = apple, banana, tomato\nred = apple, tomato, cherry\n").unwrap();
+    let result = set_algebra::evaluate("fruit - red", &fruits).unwrap();
+    assert_eq!(result, ["banana".to_string()].into_iter().collect());
+    //endregion
+
+    //region 19.7.3.位图集合BitSet
+    println!("\n\n*****=====19.7.3.位图集合BitSet=====*****");
+    // a 和 b 这样稠密的小整数，用位图存比哈希表省得多，集合运算也是一次处理 64 个元素。
+    // 性能对比：cargo run --release -- bench-sets
+    use crate::bitset::BitSet;
+    // 元素是 u32，i32 里的负数转换时就会出错，而不是变成一个巨大的下标
+    let bits_a: BitSet = a.iter().map(|&x| u32::try_from(x)).collect::<Result<_, _>>().unwrap();
+    let bits_b: BitSet = b.iter().map(|&x| u32::try_from(x)).collect::<Result<_, _>>().unwrap();
+    println!("BitSet A: {:?}, B: {:?}", bits_a, bits_b);
+    assert!([1, -1, 2].into_iter().map(u32::try_from).collect::<Result<BitSet, _>>().is_err());
+    // 和上面 HashSet<i32> 的四种运算结果一致
+    let same = |bits: Vec<u32>, hashed: Vec<&i32>| {
+        let mut hashed: Vec<u32> = hashed.into_iter().map(|&x| u32::try_from(x).unwrap()).collect();
+        hashed.sort();
+        bits == hashed
+    };
+    assert!(same(bits_a.union(&bits_b).collect(), a.union(&b).collect()));
+    assert!(same(bits_a.intersection(&bits_b).collect(), a.intersection(&b).collect()));
+    assert!(same(bits_a.difference(&bits_b).collect(), a.difference(&b).collect()));
+    assert!(same(bits_a.symmetric_difference(&bits_b).collect(), a.symmetric_difference(&b).collect()));
+
+    let mut bits: BitSet = [3, 64, 65, 200].into_iter().collect();
+    assert!(!bits.insert(64));
+    assert!(bits.contains(&200) && !bits.contains(&201) && !bits.contains(&100_000));
+    assert!(bits.remove(&3) && !bits.remove(&3));
+    assert_eq!(bits.len(), 3);
+    assert_eq!(bits.iter().collect::<Vec<_>>(), [64, 65, 200]);
+    // rank 是比 x 小的元素个数，select 是第 k 小的元素
+    assert_eq!((bits.rank(0), bits.rank(65), bits.rank(66), bits.rank(1000)), (0, 1, 2, 3));
+    assert_eq!((bits.select(0), bits.select(2), bits.select(3)), (Some(64), Some(200), None));
+    for k in 0..bits.len() {
+        assert_eq!(bits.rank(bits.select(k).unwrap()), k);
+    }
+    let small: BitSet = [64, 200].into_iter().collect();
+    assert!(small.is_subset(&bits) && bits.is_superset(&small) && !bits.is_subset(&small));
+    assert!(bits_a.is_disjoint(&small));
+    // 长度不同的位图照样可以比较和运算
+    assert_eq!(small.union(&bits_a).count(), bits_a.len() + 2);
+    assert_eq!(BitSet::with_capacity(1000), BitSet::new());
+    let dense: BitSet = (0..10_000).collect();
+    let hashed: HashSet<i32> = (0..10_000).collect();
+    println!("10000 dense integers: BitSet {} bytes, HashSet<i32> at least {} bytes",
+             dense.memory(), hashed.capacity() * std::mem::size_of::<i32>());
+    //endregion
+
+    //region 19.7.4.多重集MultiSet
+    println!("\n\n*****=====19.7.4.多重集MultiSet=====*****");
+    // HashSet 会悄悄丢掉重复的元素，MultiSet 则记住每个元素出现了几次
+    use crate::multiset::MultiSet;
+    let letters: MultiSet<char> = pangram.chars().filter(|c| c.is_alphabetic()).collect();
+    let deduped: HashSet<char> = pangram.chars().filter(|c| c.is_alphabetic()).collect();
+    println!("Pangram: {} letters, {} distinct, most common: {:?}", letters.len(), letters.distinct(), letters.most_common(2));
+    assert_eq!((letters.len(), letters.distinct(), deduped.len()), (35, 26, 26));
+    assert_eq!(letters.most_common(1), [(&'o', 4)]);
+    assert_eq!(letters.iter().count(), letters.len());
+    assert_eq!(letters.iter().filter(|&&c| c == 'e').count(), letters.count(&'e'));
+    assert_eq!(letters.counts().map(|(_, n)| n).sum::<usize>(), letters.len());
+
+    let mut bag: MultiSet<&str> = ["apple", "apple", "pear"].into_iter().collect();
+    assert_eq!(bag.insert("apple"), 2);
+    assert_eq!(bag.insert_many("fig", 3), 0);
+    assert_eq!(bag.remove(&"fig"), 3);
+    assert_eq!(bag.remove_many(&"fig", 10), 2);
+    assert!(!bag.contains(&"fig"));
+    assert_eq!(bag.remove_all(&"apple"), 3);
+    assert_eq!((bag.len(), bag.distinct()), (1, 1));
+    assert_eq!(bag.remove(&"kiwi"), 0);
+    assert!(!bag.is_empty() && MultiSet::<&str>::new().is_empty());
+
+    // {a: 3, b: 1} 和 {a: 1, b: 2, c: 1}
+    let x: MultiSet<char> = [('a', 3), ('b', 1)].into_iter().collect();
+    let y: MultiSet<char> = [('a', 1), ('b', 2), ('c', 1)].into_iter().collect();
+    println!("x = {:?}, y = {:?}", x, y);
+    println!("x ∪ y = {:?}, x + y = {:?}", x.union(&y), x.sum(&y));
+    println!("x ∩ y = {:?}, x - y = {:?}", x.intersection(&y), x.difference(&y));
+    assert_eq!(x.union(&y), [('a', 3), ('b', 2), ('c', 1)].into_iter().collect());
+    assert_eq!(x.sum(&y), [('a', 4), ('b', 3), ('c', 1)].into_iter().collect());
+    assert_eq!(x.intersection(&y), [('a', 1), ('b', 1)].into_iter().collect());
+    // 个数减到 0 的元素不会留在结果里
+    assert_eq!(x.difference(&y), [('a', 2)].into_iter().collect());
+    assert_eq!(x.difference(&y).distinct(), 1);
+    assert!(x.intersection(&y).is_subset(&x) && !x.is_subset(&y));
+    assert_eq!(x.sum(&y).len(), x.len() + y.len());
+    //endregion
+
+    //region 19.7.5.概率数据结构
+    println!("\n\n*****=====19.7.5.概率数据结构=====*****");
+    // 键多到放不下 HashSet 时，用少量内存换一点误差。下面用精确的 HashSet 算出实际误差，
+    // 误差上限的检查在 sketch 模块的测试里
+    use crate::sketch::{BloomFilter, CountingBloomFilter, HyperLogLog};
+    let members: HashSet<String> = (0..10_000).map(|i| format!("user-{}", i)).collect();
+    let strangers: Vec<String> = (0..10_000).map(|i| format!("guest-{}", i)).collect();
+    let mut bloom = BloomFilter::<str>::new(members.len(), 0.01);
+    for member in &members {
+        bloom.insert(member);
+    }
+    let false_positives = strangers.iter().filter(|s| !members.contains(*s) && bloom.contains(s)).count();
+    println!("Bloom filter: {} items, {} bits ({} bytes vs {} strings), {} hashes, false positives {:.2}% (expected {:.2}%), empty: {}",
+             bloom.len(), bloom.bits(), bloom.bits() / 8, members.len(), bloom.hashes(),
+             false_positives as f64 / strangers.len() as f64 * 100.0, bloom.expected_fp_rate() * 100.0, bloom.is_empty());
+
+    let mut counting = CountingBloomFilter::<str>::new(1000, 0.01);
+    for i in 0..1000 {
+        counting.insert(&format!("user-{}", i));
+    }
+    counting.insert("user-7");
+    println!("Counting Bloom filter: user-7 counted {} time(s)", counting.count("user-7"));
+    // 删除之后就查不到了（除非恰好误报）；user-7 插入了两次，删一次还在
+    let removed = (0..500).filter(|i| counting.remove(&format!("user-{}", i))).count();
+    let ghosts = (0..500).filter(|i| counting.contains(&format!("user-{}", i))).count();
+    println!("Counting Bloom filter: removed {}, {} items left, {} of the removed still reported (expected {:.2}%), empty: {}",
+             removed, counting.len(), ghosts, counting.expected_fp_rate() * 100.0, counting.is_empty());
+
+    // 100000 次访问里有 30000 个不同的用户
+    let visits: Vec<u64> = (0..100_000u64).map(|i| i * 7919 % 30_000).collect();
+    let exact: HashSet<u64> = visits.iter().copied().collect();
+    let mut hll = HyperLogLog::<u64>::new(12);
+    for visit in &visits {
+        hll.insert(visit);
+    }
+    let error = (hll.estimate() - exact.len() as f64).abs() / exact.len() as f64;
+    println!("HyperLogLog: estimate {:.0}, exact {}, error {:.2}% (standard error {:.2}%, {} bytes)",
+             hll.estimate(), exact.len(), error * 100.0, hll.standard_error() * 100.0, 1 << 12);
+    // 合并两个草图相当于对并集计数：0..60000 和 30000..90000 的并集有 90000 个
+    let (mut left, mut right) = (HyperLogLog::<u64>::new(12), HyperLogLog::<u64>::new(12));
+    (0..60_000).for_each(|x| left.insert(&x));
+    (30_000..90_000).for_each(|x| right.insert(&x));
+    left.merge(&right);
+    println!("HyperLogLog union: estimate {:.0}, exact 90000", left.estimate());
+    //endregion
+
+    //region 19.7.6.可插拔的哈希器
+    println!("\n\n*****=====19.7.6.可插拔的哈希器=====*****");
+    // 上面所有的 HashMap/HashSet 都用默认的 SipHash：带随机密钥，能抵御 HashDoS，但对短键偏慢。
+    // 第三个类型参数 S: BuildHasher 可以换成别的哈希算法。性能对比：cargo run --release -- bench-hashers
+    use std::hash::{BuildHasher, Hasher};
+    use crate::hashers::{FnvBuildHasher, FnvHasher, FxBuildHasher, HashState, HasherKind};
+    // FNV-1a 的标准测试向量
+    let fnv = |bytes: &[u8]| { let mut h = FnvHasher::default(); h.write(bytes); h.finish() };
+    assert_eq!(fnv(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(fnv(b"a"), 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(fnv(b"foobar"), 0x8594_4171_f739_67e8);
+
+    let mut fx_scores: HashMap<&str, i32, FxBuildHasher> = HashMap::default();
+    fx_scores.insert("Blue", 10);
+    fx_scores.insert("Yellow", 50);
+    assert_eq!(fx_scores.get("Yellow"), Some(&50));
+    // 没有密钥的哈希器每次结果都一样，SipHash 每个 RandomState 都不一样
+    assert_eq!(FxBuildHasher::default().hash_one("Blue"), FxBuildHasher::default().hash_one("Blue"));
+    assert_eq!(FnvBuildHasher::default().hash_one(42u64), FnvBuildHasher::default().hash_one(42u64));
+
+    // 集合代数的函数对任何哈希器都适用
+    let fx_a: HashSet<i32, FxBuildHasher> = a.iter().copied().collect();
+    let fx_b: HashSet<i32, FxBuildHasher> = b.iter().copied().collect();
+    let fx_union = set_algebra::union_all([&fx_a, &fx_b]);
+    assert_eq!(fx_union.len(), a.union(&b).count());
+    assert_eq!(set_algebra::relation(&fx_a, &fx_union), Relation::Subset);
+
+    // 通讯录、账号和多重集可以在运行时选哈希器，行为完全一样
+    let kind: HasherKind = "fx".parse().unwrap();
+    assert_eq!("FNV".parse::<HasherKind>(), Ok(HasherKind::Fnv));
+    assert!("md5".parse::<HasherKind>().is_err());
+    let mut fx_book = PhoneBook::with_hasher(kind);
+    let mut sip_book = PhoneBook::new();
+    for (name, phone) in [("Daniel", "798-1364"), ("Ashley", "645-7689"), ("Katie", "435-8291")] {
+        fx_book.add(name, number(phone)).unwrap();
+        sip_book.add(name, number(phone)).unwrap();
+    }
+    assert_eq!(fx_book, sip_book);
+    assert_eq!(fx_book.lookup("Katie"), Some(&number("435-8291")));
+    let mut fnv_accounts = AccountStore::with_hasher(KdfParams::FAST, HasherKind::Fnv);
+    fnv_accounts.register("j.everyman", Secret::from("correct horse"), AccountInfo::new("John Everyman", "j@example.com")).unwrap();
+    assert!(fnv_accounts.verify_password("J.Everyman", &Secret::from("correct horse")).is_ok());
+    let fnv_letters: MultiSet<char> = {
+        let mut bag = MultiSet::with_hasher(HasherKind::Fnv);
+        bag.extend(pangram.chars().filter(|c| c.is_alphabetic()));
+        bag
+    };
+    assert_eq!(fnv_letters, letters);
+    assert_eq!(fnv_letters.union(&letters).hasher(), HasherKind::Fnv);
+
+    // HashDoS：只有高 32 位不同的整数，Fx 的哈希值低位全一样，全都挤进同一个桶；
+    // SipHash 的密钥攻击者不知道，构造不出这样的键
+    let hostile = (0..1000u64).map(|i| i << 32);
+    for kind in HasherKind::ALL {
+        let buckets = crate::hashers::distinct_low_bits(&HashState::new(kind), hostile.clone(), 10);
+        println!("{:<4} 1000 hostile keys land in {:>4} of 1024 buckets; {}", kind, buckets, kind.notes());
+    }
+    assert_eq!(crate::hashers::distinct_low_bits(&FxBuildHasher::default(), hostile.clone(), 10), 1);
+    assert!(crate::hashers::distinct_low_bits(&HashState::default(), hostile, 10) > 500);
+    assert!(HasherKind::Sip.resists_hashdos() && !HasherKind::Fx.resists_hashdos());
+    //endregion
+
+    //region 19.7.7.开放寻址散列表
+    println!("\n\n*****=====19.7.7.开放寻址散列表=====*****");
+    // 19.7 开头说 HashMap 会增长也会缩小。robin_hood::RobinHoodMap 是一个手写的开放寻址散列表，
+    // 接口和 HashMap 一样，可以看到容量、负载因子和探测距离是怎么变化的。
+    // 和 std 的 HashMap 对照的差分测试在 robin_hood 模块的测试里
+    use crate::robin_hood::{Entry, RobinHoodMap};
+    let mut colors: RobinHoodMap<String, i32> = RobinHoodMap::new();
+    println!("new map, capacity {}", colors.capacity());
+    colors.insert(String::from("Blue"), 10);
+    println!("Blue overwritten, old value {:?}", colors.insert(String::from("Blue"), 25));
+    *colors.entry(String::from("Yellow")).or_insert(50) += 1;
+    colors.entry(String::from("Blue")).and_modify(|v| *v *= 2).or_insert(0);
+    // 和 HashMap 一样可以用 &str 查 String 键
+    println!("Blue {:?}, Yellow {:?}, Red {:?}", colors.get("Blue"), colors.get("Yellow"), colors.get("Red"));
+    if let Entry::Occupied(mut entry) = colors.entry(String::from("Yellow")) {
+        println!("{} was {}, replaced by 52", entry.key(), entry.get());
+        entry.insert(52);
+        println!("removed Yellow: {}", entry.remove());
+    }
+    let red = colors.entry(String::from("Red"));
+    let name = red.key().clone();
+    println!("{} defaults to {}", name, red.or_default());
+    colors.remove("Red");
+    println!("{:?} ({} entry, has Yellow: {}): {}", colors, colors.len(), colors.contains_key("Yellow"), colors.stats());
+
+    let mut squares: RobinHoodMap<u32, u32> = RobinHoodMap::default();
+    for i in 0..1000 {
+        squares.insert(i, i * i);
+    }
+    println!("after 1000 inserts: {}", squares.stats());
+    for i in 0..990 {
+        squares.remove(&i);
+    }
+    println!("after 990 removes:  {}", squares.stats());
+    *squares.get_mut(&999).unwrap() = 0;
+    println!("sum of what is left: {}", squares.iter().map(|(_, v)| *v as u64).sum::<u64>());
+    squares.clear();
+    println!("cleared, empty: {}", squares.is_empty());
+
+    // 用上一节的 Fx 加上低位全相同的键，所有键都挤在同一处，探测链很长
+    let mut colliding: RobinHoodMap<u64, u64, FxBuildHasher> = RobinHoodMap::with_hasher(FxBuildHasher::default());
+    for k in 0..300 {
+        colliding.insert(k << 32, k);
+    }
+    println!("300 colliding keys: {}", colliding.stats());
+    //endregion
+
+    //region 19.7.8.浮点数作键
+    println!("\n\n*****=====19.7.8.浮点数作键=====*****");
+    // 19.7.1 里说过 f32 和 f64 没有实现 Hash。ordered_float 提供几个包装类型，
+    // 明确规定了 NaN 和 ±0 怎么处理，于是就能用 Point 的坐标当键了
+    use std::collections::BTreeSet;
+    use crate::ordered_float::{FloatError, NotNan, OrderedFloat, Quantized};
+    let key = |p: Point| (OrderedFloat(p.x), OrderedFloat(p.y));
+    let mut landmarks: HashMap<(OrderedFloat, OrderedFloat), &str> = HashMap::new();
+    landmarks.insert(key(origin()), "origin");
+    landmarks.insert(key(Point { x: 3.0, y: 4.0 }), "corner");
+    landmarks.insert(key(Point { x: f64::NAN, y: 0.0 }), "nowhere");
+    // -0.0 和 0.0 是同一个键；NaN 和 NaN 也是同一个键
+    assert_eq!(landmarks.get(&key(Point { x: -0.0, y: 0.0 })), Some(&"origin"));
+    assert_eq!(landmarks.get(&key(Point { x: -f64::NAN, y: -0.0 })), Some(&"nowhere"));
+    assert_eq!(landmarks.get(&key(Point { x: 3.0, y: 4.000001 })), None);
+    // 排序时 NaN 排在最后，-0.0 和 0.0 算重复
+    let sorted: Vec<f64> = [2.5, f64::NAN, -1.0, 0.0, f64::INFINITY, -0.0, f64::NEG_INFINITY].into_iter()
+        .map(OrderedFloat).collect::<BTreeSet<_>>().into_iter().map(OrderedFloat::into_inner).collect();
+    println!("sorted: {:?}", sorted);
+    assert_eq!(sorted.len(), 6);
+    assert!(sorted[..5] == [f64::NEG_INFINITY, -1.0, 0.0, 2.5, f64::INFINITY] && sorted[5].is_nan());
+
+    // NotNan 在构造时就拒绝 NaN，之后就是普通的数值比较
+    assert_eq!(NotNan::new(f64::NAN), Err(FloatError::NaN));
+    assert_eq!(NotNan::try_from(0.0), NotNan::new(-0.0));
+    let mut distances: HashMap<NotNan, &str> = HashMap::new();
+    distances.insert(NotNan::new(5.0).unwrap(), "corner");
+    assert_eq!(distances.get(&NotNan::new((3.0f64 * 3.0 + 4.0 * 4.0).sqrt()).unwrap()), Some(&"corner"));
+    assert_eq!(NotNan::new(1.5).unwrap().max(NotNan::new(-2.0).unwrap()).into_inner(), 1.5);
+
+    // 计算出来的坐标往往有误差：0.1 + 0.2 != 0.3。量化的键把相差不到一个步长的坐标放进同一个桶
+    assert_ne!(OrderedFloat(0.1 + 0.2), OrderedFloat(0.3));
+    const TOLERANCE: f64 = 0.001;
+    let grid = |p: Point| -> Result<(Quantized, Quantized), FloatError> {
+        Ok((Quantized::new(p.x, TOLERANCE)?, Quantized::new(p.y, TOLERANCE)?))
+    };
+    let mut stations: HashMap<(Quantized, Quantized), &str> = HashMap::new();
+    stations.insert(grid(Point { x: 0.3, y: 1.2 }).unwrap(), "north");
+    stations.insert(grid(Point { x: 3.0, y: 4.0 }).unwrap(), "east");
+    assert_eq!(stations.get(&grid(Point { x: 0.1 + 0.2, y: 0.4 * 3.0 }).unwrap()), Some(&"north"));
+    assert_eq!(grid(Point { x: f64::NAN, y: 0.0 }), Err(FloatError::NaN));
+    assert_eq!(grid(Point { x: f64::INFINITY, y: 0.0 }), Err(FloatError::NotFinite));
+    assert_eq!(Quantized::new(1.0, 0.0), Err(FloatError::InvalidTolerance));
+    // 3.0006 和 3.0 只差 0.0006，却被四舍五入到了相邻的桶，所以“附近”查找要连相邻的桶一起查，
+    // 再按真实距离过滤
+    let near = |p: Point| -> Option<&str> {
+        let (qx, qy) = grid(p).ok()?;
+        qx.neighbors().into_iter()
+            .flat_map(|x| qy.neighbors().into_iter().map(move |y| (x, y)))
+            .filter(|&(x, y)| (x.center(TOLERANCE) - p.x).abs() <= TOLERANCE && (y.center(TOLERANCE) - p.y).abs() <= TOLERANCE)
+            .find_map(|cell| stations.get(&cell).copied())
+    };
+    let drifted = Point { x: 3.0006, y: 4.0 };
+    assert_eq!(stations.get(&grid(drifted).unwrap()), None);
+    assert_eq!(near(drifted), Some("east"));
+    assert_eq!(near(Point { x: 3.01, y: 4.0 }), None);
+    println!("{:?} is near {:?}, bucket {}", drifted, near(drifted), Quantized::new(drifted.x, TOLERANCE).unwrap().bucket());
     //endregion
 
     //region 19.8.引用计数Rc
@@ -471,6 +1295,142 @@ fn main() {
         println!("Reference Count of rc_a: {}", Rc::strong_count(&rc_a));
         println!("--- rc_a is dropped out of scope ---");
     }
+
+    // 一棵树：父节点用 Rc<RefCell<Node>> 拥有子节点，子节点用 Weak 指回父节点（见 tree 模块）。
+    // 节点的值在被释放时把名字记到 dropped 里，用来证明树拆掉之后所有节点都被释放了、没有泄漏
+    use std::cell::RefCell;
+    use std::fmt;
+    use crate::tree::NodeRef;
+    struct Tracked {
+        name: &'static str,
+        dropped: Rc<RefCell<Vec<&'static str>>>,
+    }
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.dropped.borrow_mut().push(self.name);
+        }
+    }
+    impl fmt::Display for Tracked {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.name)
+        }
+    }
+    let dropped = Rc::new(RefCell::new(Vec::new()));
+    let tracked = |name| Tracked { name, dropped: Rc::clone(&dropped) };
+    let names = |nodes: Vec<NodeRef<Tracked>>| nodes.iter().map(|node| node.value().name).collect::<Vec<_>>();
+    {
+        println!("--- build a tree ---");
+        let root = NodeRef::new(tracked("root"));
+        let docs = root.add_child(tracked("docs"));
+        let src = root.add_child(tracked("src"));
+        docs.add_child(tracked("README"));
+        let main_rs = src.add_child(tracked("main.rs"));
+        src.add_child(tracked("lib.rs"));
+        print!("{}", root);
+        // root 只有一个所有者（变量 root），两个子节点各有一个 Weak 指回它；
+        // src 被 root 和变量 src 两个所有者持有
+        assert_eq!((root.strong_count(), root.weak_count()), (1, 2));
+        assert_eq!((src.strong_count(), src.weak_count()), (2, 2));
+        assert_eq!((main_rs.strong_count(), main_rs.weak_count()), (2, 0));
+        assert!(main_rs.parent().unwrap() == src && main_rs.depth() == 2);
+        assert_eq!(names(main_rs.ancestors().collect()), ["src", "root"]);
+        assert_eq!(names(root.preorder()), ["root", "docs", "README", "src", "main.rs", "lib.rs"]);
+        assert_eq!(names(root.breadth_first()), ["root", "docs", "src", "README", "main.rs", "lib.rs"]);
+
+        println!("--- move main.rs under docs, then remove src (its children go up to root) ---");
+        docs.append(&main_rs);
+        assert!(src.remove());
+        print!("{}", root);
+        assert_eq!(names(root.children()), ["docs", "lib.rs"]);
+        assert!(src.parent().is_none() && src.children().is_empty());
+        // src 已经不在树里，只剩变量 src 一个所有者
+        assert_eq!((src.strong_count(), src.weak_count()), (1, 0));
+        drop(src);
+        assert_eq!(*dropped.borrow(), ["src"]);
+
+        println!("--- detach docs with its subtree ---");
+        let readme = root.find(|value| value.name == "README").unwrap();
+        assert!(docs.detach() && !docs.detach());
+        assert!(readme.ancestors().last().unwrap() == docs);
+        print!("{}{}", root, docs);
+        drop(docs);
+        // 摘下来的 docs 只有变量 docs 一个所有者。readme 和 main_rs 还活着，但它们只用 Weak 指向 docs，
+        // 不会让 docs 多活一刻
+        assert_eq!(*dropped.borrow(), ["src", "docs"]);
+        assert!(readme.parent().is_none());
+        root.value_mut().name = "ROOT";
+        println!("--- root, readme and main_rs are dropped out of scope ---");
+    }
+    // 所有节点都被释放了，每个恰好一次
+    let mut all = dropped.borrow().clone();
+    all.sort();
+    println!("Dropped: {:?}", dropped.borrow());
+    assert_eq!(all, ["README", "ROOT", "docs", "lib.rs", "main.rs", "src"]);
+
+    // 上面的树用 Weak 指回父节点。如果子节点用 Rc 指回父节点，父子互相持有，
+    // 计数永远到不了 0，离开作用域也不会释放，这就是 Rc 的引用环泄漏。
+    // leak_check::TrackedRc 记下每个句柄被谁持有，checkpoint 能找出环和已经泄漏的分配。
+    // 自己指向自己、所有者先释放和十万个节点的长环在 leak_check 模块的测试里
+    use crate::leak_check::{Tracker, TrackedRc, TrackedWeak};
+    struct Parent {
+        children: RefCell<Vec<TrackedRc<Child>>>,
+    }
+    struct Child {
+        parent: RefCell<Option<TrackedRc<Parent>>>,
+        // 只被子节点持有的数据，子节点泄漏了它也跟着泄漏
+        payload: RefCell<Option<TrackedRc<String>>>,
+    }
+    // 错误的写法：子节点用强引用指回父节点
+    let family = |tracker: &Tracker| {
+        let parent = TrackedRc::new(tracker, Parent { children: RefCell::new(Vec::new()) });
+        for name in ["Alice", "Bob"] {
+            let child = TrackedRc::new(tracker, Child { parent: RefCell::new(None), payload: RefCell::new(None) });
+            *child.payload.borrow_mut() = Some(TrackedRc::new(tracker, name.to_string()).into_owned_by(&child));
+            *child.parent.borrow_mut() = Some(parent.owned_by(&child));
+            parent.children.borrow_mut().push(child.into_owned_by(&parent));
+        }
+        parent
+    };
+
+    println!("--- a parent and two children holding strong references to each other ---");
+    let tracker = Tracker::new();
+    let parent = family(&tracker);
+    // 还有根句柄 parent，环是可达的，暂时没有泄漏
+    print!("{}", tracker.checkpoint());
+    // 把子节点指回父节点的引用拿掉，环就断了，放掉 parent 后全部释放
+    for child in parent.children.borrow().iter() {
+        child.parent.borrow_mut().take();
+    }
+    println!("after breaking the cycle: parent has {} owner(s), {} cycle(s)",
+             parent.strong_count(), tracker.checkpoint().cycles.len());
+    drop(parent);
+    println!("{} live allocation(s)", tracker.live());
+
+    println!("--- the same family, but the last handle is dropped without breaking the cycle ---");
+    let parent = family(&tracker);
+    let rescue: TrackedWeak<Parent> = parent.downgrade();
+    drop(parent);
+    let report = tracker.checkpoint();
+    print!("{}", report);
+    println!("clean: {}, leaked types: {:?}", report.is_clean(), report.leaked_types());
+    // 这里留了一个 Weak 作后门，才能把环拆掉；普通代码里这些内存就再也回收不了了
+    let parent = rescue.upgrade().unwrap();
+    parent.children.borrow_mut().clear();
+    drop(parent);
+    println!("{} live allocation(s), rescue still upgrades: {}", tracker.live(), rescue.upgrade().is_some());
+
+    println!("--- a node that holds itself ---");
+    struct Selfish {
+        me: RefCell<Option<TrackedRc<Selfish>>>,
+    }
+    let selfish = TrackedRc::new(&tracker, Selfish { me: RefCell::new(None) });
+    *selfish.me.borrow_mut() = Some(selfish.owned_by(&selfish));
+    println!("Selfish#{} holds itself: {}", selfish.id(), selfish.me.borrow().as_ref().unwrap().ptr_eq(&selfish));
+    let escape = selfish.downgrade();
+    drop(selfish);
+    print!("{}", tracker.checkpoint());
+    escape.upgrade().unwrap().me.borrow_mut().take();
+    println!("{} live allocation(s)", tracker.live());
     //endregion
 
     //region 19.9.共享引用计数Arc
@@ -567,4 +1527,6397 @@ mod checked_v1 {
             Ok(value) => println!("{}", value),
         }
     }
-}
\ No newline at end of file
+}
+mod division {
+    use std::num::ParseIntError;
+
+    // 19.4 的 checked_division：除数为 0 时返回 None
+    // i32::MIN / -1 的结果超出了 i32 的范围，直接用 / 会 panic，这里同样返回 None
+    pub fn checked_division(dividend: i32, divisor: i32) -> Option<i32> {
+        if divisor == 0 {
+            None
+        } else {
+            dividend.checked_div(divisor)
+        }
+    }
+
+    // 依次除以 divisors 中的每个数，? 遇到 None 会直接返回 None
+    pub fn checked_chain(dividend: i32, divisors: &[i32]) -> Option<i32> {
+        let mut quotient = dividend;
+        for &divisor in divisors {
+            quotient = checked_division(quotient, divisor)?;
+        }
+        Some(quotient)
+    }
+
+    // 整数平均值，空切片没有平均值
+    pub fn average(values: &[i32]) -> Option<i32> {
+        let len = i32::try_from(values.len()).ok()?;
+        checked_division(values.iter().sum(), len)
+    }
+
+    // 除数是可选的：先用 transpose 把 Option<Result<..>> 翻成 Result<Option<..>>，
+    // 这样解析错误可以用 ? 抛出，而“没有除数”和“除以 0”都得到 Ok(None)
+    pub fn parse_and_divide(dividend: &str, divisor: Option<&str>) -> Result<Option<i32>, ParseIntError> {
+        let dividend: i32 = dividend.parse()?;
+        let divisor = divisor.map(str::parse::<i32>).transpose()?;
+        Ok(divisor.and_then(|divisor| checked_division(dividend, divisor)))
+    }
+}
+
+mod option_ext {
+    // 给 Option 加几个常用的辅助方法
+    pub trait OptionExt<T> {
+        // None 时把 msg 打印到 stderr，并返回 default
+        fn unwrap_or_log(self, default: T, msg: &str) -> T;
+        // 和 expect 一样，但 panic 信息是按需生成的（只有在 None 时才会去格式化）
+        fn expect_with<F: FnOnce() -> String>(self, msg: F) -> T;
+        // None 时执行 f，然后原样返回自己，方便在链式调用中间插入一个副作用
+        fn on_none<F: FnOnce()>(self, f: F) -> Self;
+    }
+
+    impl<T> OptionExt<T> for Option<T> {
+        fn unwrap_or_log(self, default: T, msg: &str) -> T {
+            match self {
+                Some(value) => value,
+                None => {
+                    eprintln!("{}", msg);
+                    default
+                }
+            }
+        }
+
+        fn expect_with<F: FnOnce() -> String>(self, msg: F) -> T {
+            match self {
+                Some(value) => value,
+                None => panic!("{}", msg()),
+            }
+        }
+
+        fn on_none<F: FnOnce()>(self, f: F) -> Self {
+            if self.is_none() {
+                f();
+            }
+            self
+        }
+    }
+}
+
+// 最简单的 CSV：字段里有逗号、引号时用双引号括起来，引号写两遍。batch 的输出和通讯录文件共用
+mod csv {
+    pub fn escape(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    pub fn split(line: &str) -> Result<Vec<String>, &'static str> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut chars = line.chars().peekable();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (false, '"') if field.is_empty() => quoted = true,
+                (false, ',') => fields.push(std::mem::take(&mut field)),
+                (_, c) => field.push(c),
+            }
+        }
+        if quoted {
+            return Err("unterminated quoted field");
+        }
+        fields.push(field);
+        Ok(fields)
+    }
+}
+
+// 原子地替换整个文件：写临时文件 -> fsync -> rename -> fsync 目录。
+// 任何一步崩溃，目标文件要么是完整的旧版本，要么是完整的新版本。通讯录和账号库共用
+mod atomic_file {
+    use std::fs::{self, File};
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut tmp_name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let result = (|| {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(contents)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, path)?;
+            // 目录项的变化也要落盘，否则断电后 rename 可能丢失（Windows 上打不开目录，忽略）
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                if let Ok(dir) = File::open(dir) {
+                    let _ = dir.sync_all();
+                }
+            }
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+}
+
+mod batch {
+    use std::fmt;
+    use std::io::{self, BufRead, Write};
+    use crate::csv;
+    use crate::division::checked_division;
+
+    // 最多记下多少个失败的行号，避免大文件全是错误时 summary 无限增长
+    const MAX_REPORTED_LINES: usize = 20;
+
+    #[derive(Debug, PartialEq)]
+    pub enum RowError {
+        // 一行不是合法的 CSV（比如引号没有闭合）
+        InvalidCsv(&'static str),
+        // 一行不是恰好两个字段
+        WrongFieldCount(usize),
+        InvalidNumber(String),
+        DivisionByZero,
+        Overflow,
+    }
+
+    impl fmt::Display for RowError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                RowError::InvalidCsv(why) => write!(f, "invalid CSV: {}", why),
+                RowError::WrongFieldCount(n) => write!(f, "expected 2 fields, found {}", n),
+                RowError::InvalidNumber(field) => write!(f, "invalid number {:?}", field),
+                RowError::DivisionByZero => write!(f, "division by zero"),
+                RowError::Overflow => write!(f, "overflow"),
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Summary {
+        pub rows: usize,
+        pub succeeded: usize,
+        pub division_by_zero: usize,
+        pub overflow: usize,
+        pub malformed: usize,
+        // 前 MAX_REPORTED_LINES 个失败行的行号（从 1 开始）
+        pub failed_lines: Vec<usize>,
+    }
+
+    impl Summary {
+        pub fn failed(&self) -> usize {
+            self.rows - self.succeeded
+        }
+
+        fn record_failure(&mut self, line_no: usize, error: &RowError) {
+            match error {
+                RowError::DivisionByZero => self.division_by_zero += 1,
+                RowError::Overflow => self.overflow += 1,
+                RowError::InvalidCsv(_) | RowError::WrongFieldCount(_) | RowError::InvalidNumber(_) => self.malformed += 1,
+            }
+            if self.failed_lines.len() < MAX_REPORTED_LINES {
+                self.failed_lines.push(line_no);
+            }
+        }
+    }
+
+    impl fmt::Display for Summary {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{} rows, {} succeeded, {} failed (division by zero: {}, overflow: {}, malformed: {})",
+                   self.rows, self.succeeded, self.failed(),
+                   self.division_by_zero, self.overflow, self.malformed)?;
+            if !self.failed_lines.is_empty() {
+                let lines: Vec<String> = self.failed_lines.iter().map(|n| n.to_string()).collect();
+                write!(f, "; failed lines: {}", lines.join(", "))?;
+                if self.failed() > self.failed_lines.len() {
+                    write!(f, ", ...")?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn parse_field(field: &str) -> Result<i32, RowError> {
+        field.trim().parse().map_err(|_| RowError::InvalidNumber(field.trim().to_string()))
+    }
+
+    // 解析 "被除数,除数" 并做除法
+    pub fn divide_row(line: &str) -> Result<(i32, i32, i32), RowError> {
+        let fields = csv::split(line).map_err(RowError::InvalidCsv)?;
+        if fields.len() != 2 {
+            return Err(RowError::WrongFieldCount(fields.len()));
+        }
+        let dividend = parse_field(&fields[0])?;
+        let divisor = parse_field(&fields[1])?;
+        match checked_division(dividend, divisor) {
+            Some(quotient) => Ok((dividend, divisor, quotient)),
+            None if divisor == 0 => Err(RowError::DivisionByZero),
+            None => Err(RowError::Overflow),
+        }
+    }
+
+    // 逐行读取 input，每行结果写到 output：
+    //      line,dividend,divisor,status,result
+    // 空行、# 开头的注释行以及第一行的 "dividend,divisor" 表头会被跳过
+    pub fn run<R: BufRead, W: Write>(input: R, mut output: W) -> io::Result<Summary> {
+        let mut summary = Summary::default();
+        writeln!(output, "line,dividend,divisor,status,result")?;
+        for (index, line) in input.lines().enumerate() {
+            let line = line?;
+            let line_no = index + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if line_no == 1 && trimmed.eq_ignore_ascii_case("dividend,divisor") {
+                continue;
+            }
+            summary.rows += 1;
+            match divide_row(trimmed) {
+                Ok((dividend, divisor, quotient)) => {
+                    summary.succeeded += 1;
+                    writeln!(output, "{},{},{},ok,{}", line_no, dividend, divisor, quotient)?;
+                }
+                Err(why) => {
+                    summary.record_failure(line_no, &why);
+                    // 原始内容里可能有逗号，出错时只写行号和原因
+                    writeln!(output, "{},,,error,{}", line_no, csv::escape(&why.to_string()))?;
+                }
+            }
+        }
+        output.flush()?;
+        Ok(summary)
+    }
+}
+
+mod cli {
+    use std::fs::File;
+    use std::io::{self, BufReader, BufWriter};
+
+    const USAGE: &str = "usage: rust_by_example_19 <command> [args]
+
+commands:
+    divide [FILE]             divide each \"dividend,divisor\" row of FILE (or stdin)
+    call ROUTES NUMBER...     dial NUMBERs using the call routes defined in ROUTES
+    lookup CONTACTS           search the CONTACTS file for each name or number read from stdin
+    sets FILE EXPR            evaluate a set expression such as \"(A | B) - C\" over the sets in FILE
+    bench-sets [UNIVERSE]     time BitSet against HashSet<i32> and BTreeSet<i32> at several densities
+    bench-hashers [N]         time SipHash, FxHash and FNV on integer, string and adversarial keys";
+
+    // 返回进程的退出码
+    pub fn run(args: &[String]) -> i32 {
+        let result = match args[0].as_str() {
+            "divide" => divide(&args[1..]),
+            "call" => call(&args[1..]),
+            "lookup" => lookup(&args[1..]),
+            "sets" => sets(&args[1..]),
+            "bench-sets" => bench_sets(&args[1..]),
+            "bench-hashers" => bench_hashers(&args[1..]),
+            "help" | "-h" | "--help" => {
+                println!("{}", USAGE);
+                Ok(0)
+            }
+            other => Err(format!("unknown command {:?}\n{}", other, USAGE)),
+        };
+        match result {
+            Ok(code) => code,
+            Err(why) => {
+                eprintln!("error: {}", why);
+                2
+            }
+        }
+    }
+
+    fn divide(args: &[String]) -> Result<i32, String> {
+        let stdout = io::stdout();
+        let output = BufWriter::new(stdout.lock());
+        let summary = match args.first().map(String::as_str) {
+            None | Some("-") => crate::batch::run(io::stdin().lock(), output),
+            Some(path) => {
+                let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+                crate::batch::run(BufReader::new(file), output)
+            }
+        }.map_err(|e| e.to_string())?;
+        eprintln!("{}", summary);
+        Ok(if summary.failed() == 0 { 0 } else { 1 })
+    }
+
+    fn call(args: &[String]) -> Result<i32, String> {
+        use crate::call_router::{CallLog, CallRouter};
+        use crate::clock::SystemClock;
+        let (routes, numbers) = match args {
+            [routes, numbers @ ..] if !numbers.is_empty() => (routes, numbers),
+            _ => return Err(format!("call needs a routes file and at least one number\n{}", USAGE)),
+        };
+        let router = CallRouter::load(routes).map_err(|e| format!("{}: {}", routes, e))?;
+        let mut log = CallLog::new(SystemClock);
+        for number in numbers {
+            println!("{}: {}", number, log.call(&router, number).outcome);
+        }
+        for record in log.records() {
+            eprintln!("{}", record);
+        }
+        Ok(0)
+    }
+
+    // 每读一行查询就打印一次结果，空行或 EOF 结束
+    fn lookup(args: &[String]) -> Result<i32, String> {
+        use std::io::{BufRead, Write};
+        use crate::contact_search::search;
+        use crate::phone_book::PhoneBook;
+        let path = args.first().ok_or_else(|| format!("lookup needs a contacts file\n{}", USAGE))?;
+        let book = PhoneBook::load(path).map_err(|e| format!("{}: {}", path, e))?;
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("lookup> ");
+            io::stdout().flush().map_err(|e| e.to_string())?;
+            let query = match lines.next() {
+                Some(line) => line.map_err(|e| e.to_string())?,
+                None => break,
+            };
+            if query.trim().is_empty() {
+                break;
+            }
+            let hits = search(&book, &query);
+            if hits.is_empty() {
+                println!("  no match");
+            }
+            for hit in hits {
+                println!("  {}: {} ({:?})", hit.name, hit.number, hit.kind);
+            }
+        }
+        println!();
+        Ok(0)
+    }
+
+    fn sets(args: &[String]) -> Result<i32, String> {
+        use crate::set_algebra::{evaluate, load_sets};
+        let (path, expr) = match args {
+            [path, expr] => (path, expr),
+            _ => return Err(format!("sets needs a file and an expression\n{}", USAGE)),
+        };
+        let sets = load_sets(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut result: Vec<String> = evaluate(expr, &sets).map_err(|e| e.to_string())?.into_iter().collect();
+        result.sort();
+        println!("{}", result.join(", "));
+        Ok(0)
+    }
+
+    fn bench_sets(args: &[String]) -> Result<i32, String> {
+        let universe: u32 = match args.first() {
+            Some(n) => n.parse().map_err(|e| format!("invalid universe {:?}: {}", n, e))?,
+            None => 1_000_000,
+        };
+        if i32::try_from(universe).is_err() {
+            return Err(format!("universe {} is larger than i32::MAX", universe));
+        }
+        println!("{:>8} {:<14} {:>12} {:>12} {:>12}", "density", "structure", "build", "lookup", "intersection");
+        for density in [0.001, 0.01, 0.1, 0.5, 0.9] {
+            for result in crate::bitset::benchmark(universe, density) {
+                println!("{:>8} {:<14} {:>12?} {:>12?} {:>12?}",
+                         density, result.structure, result.build, result.lookup, result.intersection);
+            }
+        }
+        Ok(0)
+    }
+
+    fn bench_hashers(args: &[String]) -> Result<i32, String> {
+        let n = match args.first() {
+            Some(n) => n.parse().map_err(|e| format!("invalid count {:?}: {}", n, e))?,
+            None => 1_000_000,
+        };
+        // 对抗性的键会让 Fx 退化成 O(n^2)，数量要小得多
+        let adversarial = (n / 50).min(20_000);
+        println!("{:<5} {:>12} {:>12} {:>12} {:>12} {:>16}", "hash", "int insert", "int lookup", "str insert", "str lookup",
+                 format!("{} hostile", adversarial));
+        let results = crate::hashers::benchmark(n, adversarial);
+        for r in &results {
+            println!("{:<5} {:>12?} {:>12?} {:>12?} {:>12?} {:>16?}",
+                     r.hasher, r.int_insert, r.int_lookup, r.str_insert, r.str_lookup, r.adversarial_insert);
+        }
+        println!();
+        for r in &results {
+            println!("{:<5} {}: {}", r.hasher, if r.hasher.resists_hashdos() { "HashDoS-resistant" } else { "not HashDoS-resistant" },
+                     r.hasher.notes());
+        }
+        Ok(0)
+    }
+}
+
+mod phone_book {
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::fmt;
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader};
+    use std::path::Path;
+    use crate::atomic_file;
+    use crate::csv;
+    use crate::hashers::{HashState, HasherKind};
+    use crate::phone_number::PhoneNumber;
+
+    #[derive(Debug)]
+    pub enum PhoneBookError {
+        NotFound(String),
+        AlreadyExists(String),
+        Io(io::Error),
+        // 文件第 line 行格式不对
+        Parse { line: usize, reason: String },
+    }
+
+    // io::Error 没有实现 PartialEq，这里只比较错误的种类
+    impl PartialEq for PhoneBookError {
+        fn eq(&self, other: &Self) -> bool {
+            match (self, other) {
+                (PhoneBookError::NotFound(a), PhoneBookError::NotFound(b)) => a == b,
+                (PhoneBookError::AlreadyExists(a), PhoneBookError::AlreadyExists(b)) => a == b,
+                (PhoneBookError::Io(a), PhoneBookError::Io(b)) => a.kind() == b.kind(),
+                (PhoneBookError::Parse { line: a, .. }, PhoneBookError::Parse { line: b, .. }) => a == b,
+                _ => false,
+            }
+        }
+    }
+
+    impl fmt::Display for PhoneBookError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                PhoneBookError::NotFound(name) => write!(f, "no contact named {:?}", name),
+                PhoneBookError::AlreadyExists(name) => write!(f, "contact {:?} already exists", name),
+                PhoneBookError::Io(e) => write!(f, "{}", e),
+                PhoneBookError::Parse { line, reason } => write!(f, "line {}: {}", line, reason),
+            }
+        }
+    }
+
+    impl From<io::Error> for PhoneBookError {
+        fn from(e: io::Error) -> Self {
+            PhoneBookError::Io(e)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Entry {
+        number: PhoneNumber,
+        // 插入序号，替换号码时不变
+        seq: u64,
+    }
+
+    // HashMap 负责 O(1) 按名字查找，旁边另外维护几个有序索引，提供确定顺序的遍历：
+    //      by_insertion：插入序号 -> 名字
+    //      by_name：按名字排序
+    //      by_number：按号码排序（同一个号码按名字排），顺便让按号码反查不用再扫一遍
+    // 每次增删改都同时更新这几个索引
+    #[derive(Debug, Default)]
+    pub struct PhoneBook {
+        entries: HashMap<String, Entry, HashState>,
+        by_insertion: BTreeMap<u64, String>,
+        by_name: BTreeSet<String>,
+        by_number: BTreeSet<(PhoneNumber, String)>,
+        next_seq: u64,
+    }
+
+    // 两个通讯录内容和插入顺序都一样就相等，不管中间删过多少次（序号不同）
+    impl PartialEq for PhoneBook {
+        fn eq(&self, other: &Self) -> bool {
+            self.len() == other.len() && self.list().eq(other.list())
+        }
+    }
+
+    impl PhoneBook {
+        pub fn new() -> Self {
+            PhoneBook::default()
+        }
+
+        // 名字来自外部输入时保持默认的 SipHash，见 hashers::HasherKind::notes
+        pub fn with_hasher(kind: HasherKind) -> Self {
+            PhoneBook { entries: HashMap::with_hasher(HashState::new(kind)), ..PhoneBook::default() }
+        }
+
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.entries.is_empty()
+        }
+
+        // 新增或替换，返回被替换掉的旧号码（与 HashMap::insert 一致）。
+        // 替换号码不改变联系人在插入顺序中的位置
+        pub fn insert(&mut self, name: &str, number: PhoneNumber) -> Option<PhoneNumber> {
+            if let Some(entry) = self.entries.get_mut(name) {
+                let old = std::mem::replace(&mut entry.number, number.clone());
+                self.by_number.remove(&(old.clone(), name.to_string()));
+                self.by_number.insert((number, name.to_string()));
+                return Some(old);
+            }
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            self.entries.insert(name.to_string(), Entry { number: number.clone(), seq });
+            self.by_insertion.insert(seq, name.to_string());
+            self.by_name.insert(name.to_string());
+            self.by_number.insert((number, name.to_string()));
+            None
+        }
+
+        // 只新增，名字已存在时报错
+        pub fn add(&mut self, name: &str, number: PhoneNumber) -> Result<(), PhoneBookError> {
+            if self.entries.contains_key(name) {
+                return Err(PhoneBookError::AlreadyExists(name.to_string()));
+            }
+            self.insert(name, number);
+            Ok(())
+        }
+
+        // 只修改已有的联系人，返回旧号码
+        pub fn update(&mut self, name: &str, number: PhoneNumber) -> Result<PhoneNumber, PhoneBookError> {
+            if !self.entries.contains_key(name) {
+                return Err(PhoneBookError::NotFound(name.to_string()));
+            }
+            Ok(self.insert(name, number).expect("contact exists"))
+        }
+
+        pub fn remove(&mut self, name: &str) -> Result<PhoneNumber, PhoneBookError> {
+            let entry = self.entries.remove(name).ok_or_else(|| PhoneBookError::NotFound(name.to_string()))?;
+            self.by_insertion.remove(&entry.seq);
+            self.by_name.remove(name);
+            let key = (entry.number, name.to_string());
+            self.by_number.remove(&key);
+            Ok(key.0)
+        }
+
+        // String 作键时，get 可以直接传 &str
+        pub fn lookup(&self, name: &str) -> Option<&PhoneNumber> {
+            self.entries.get(name).map(|entry| &entry.number)
+        }
+
+        // 按号码反查联系人。号码都是规范化之后再比较的，
+        // 所以 "798 1364" 和 "798-1364" 查到的是同一个人
+        pub fn find_by_number(&self, number: &PhoneNumber) -> Option<&str> {
+            self.by_number.range((number.clone(), String::new())..)
+                .next()
+                .filter(|(candidate, _)| candidate == number)
+                .map(|(_, name)| name.as_str())
+        }
+
+        // 按插入顺序遍历，每次运行的输出都一样
+        pub fn list(&self) -> impl Iterator<Item = (&str, &PhoneNumber)> {
+            self.by_insertion.values().map(move |name| self.entry(name))
+        }
+
+        // 按名字排序遍历
+        pub fn sorted_by_name(&self) -> impl Iterator<Item = (&str, &PhoneNumber)> {
+            self.by_name.iter().map(move |name| self.entry(name))
+        }
+
+        // 按号码排序遍历
+        pub fn sorted_by_number(&self) -> impl Iterator<Item = (&str, &PhoneNumber)> {
+            self.by_number.iter().map(|(number, name)| (name.as_str(), number))
+        }
+
+        fn entry<'a>(&'a self, name: &'a str) -> (&'a str, &'a PhoneNumber) {
+            (name, &self.entries[name].number)
+        }
+
+        // 带引号的字段里可以有换行（名字里有换行时 save 就会这样写），这时一条记录跨好几行
+        pub fn load<P: AsRef<Path>>(path: P) -> Result<PhoneBook, PhoneBookError> {
+            let reader = BufReader::new(File::open(path)?);
+            let mut book = PhoneBook::new();
+            // 还没读完的记录：起始行号和已经读到的内容
+            let mut pending: Option<(usize, String)> = None;
+            for (index, line) in reader.lines().enumerate() {
+                let line = line?;
+                let (start, record) = match pending.take() {
+                    Some((start, record)) => (start, record + "\n" + &line),
+                    None if line.trim().is_empty() => continue,
+                    None => (index + 1, line),
+                };
+                let parse_error = |reason: &str| PhoneBookError::Parse { line: start, reason: reason.to_string() };
+                // split 只在引号没有闭合时出错，说明记录还没完，接着读下一行
+                let Ok(fields) = csv::split(&record) else {
+                    pending = Some((start, record));
+                    continue;
+                };
+                match fields.as_slice() {
+                    [name, number] => {
+                        let number = PhoneNumber::parse(number).map_err(|e| parse_error(&e.to_string()))?;
+                        if book.insert(name, number).is_some() {
+                            return Err(parse_error("duplicate contact"));
+                        }
+                    }
+                    _ => return Err(parse_error("expected name,number")),
+                }
+            }
+            if let Some((line, _)) = pending {
+                return Err(PhoneBookError::Parse { line, reason: "unterminated quoted field".to_string() });
+            }
+            Ok(book)
+        }
+
+        // 整个文件原子地替换，见 atomic_file::write
+        pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PhoneBookError> {
+            let mut contents = String::new();
+            // 按插入顺序写出，文件内容稳定，加载回来顺序也不变
+            for (name, number) in self.list() {
+                contents.push_str(&format!("{},{}\n", csv::escape(name), csv::escape(&number.to_string())));
+            }
+            Ok(atomic_file::write(path.as_ref(), contents.as_bytes())?)
+        }
+    }
+
+}
+
+mod phone_number {
+    use std::fmt;
+    use std::str::FromStr;
+
+    // 没有国家码或国家码为 1 时，按北美格式（NANP）处理：7 位本地号码，或 3 位区号 + 7 位本地号码
+    const DEFAULT_COUNTRY_CODE: u16 = 1;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum PhoneNumberError {
+        Empty,
+        InvalidCharacter(char),
+        // + 只能出现在开头
+        MisplacedPlus,
+        UnbalancedParentheses,
+        InvalidCountryCode,
+        // 去掉分隔符之后的数字个数不对
+        InvalidLength(usize),
+    }
+
+    impl fmt::Display for PhoneNumberError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                PhoneNumberError::Empty => write!(f, "empty phone number"),
+                PhoneNumberError::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+                PhoneNumberError::MisplacedPlus => write!(f, "'+' is only allowed before the country code"),
+                PhoneNumberError::UnbalancedParentheses => write!(f, "unbalanced parentheses"),
+                PhoneNumberError::InvalidCountryCode => write!(f, "country code must be 1 to 3 digits"),
+                PhoneNumberError::InvalidLength(n) => write!(f, "unexpected number of digits: {}", n),
+            }
+        }
+    }
+
+    // 规范化之后的号码：只保留数字，国家码为 1 时不单独保存。
+    // 所以 "798-1364"、"798 1364"、"798.1364" 解析出来是同一个值，
+    // "(555) 798-1364"、"555.798.1364"、"+1 555 798 1364"、"1-555-798-1364" 也是同一个值。
+    // 派生的 Eq 和 Hash 都基于规范形式，可以直接拿来做 HashMap 的键
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct PhoneNumber {
+        // None 表示默认的国家码 1
+        country_code: Option<u16>,
+        // 国内号码的全部数字（含区号）
+        national: String,
+    }
+
+    impl PhoneNumber {
+        pub fn parse(input: &str) -> Result<PhoneNumber, PhoneNumberError> {
+            let input = input.trim();
+            if input.is_empty() {
+                return Err(PhoneNumberError::Empty);
+            }
+            let (country_code, rest) = match input.strip_prefix('+') {
+                Some(rest) => {
+                    let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                    if len == 0 || len > 3 {
+                        return Err(PhoneNumberError::InvalidCountryCode);
+                    }
+                    let code: u16 = rest[..len].parse().map_err(|_| PhoneNumberError::InvalidCountryCode)?;
+                    (Some(code), &rest[len..])
+                }
+                None => (None, input),
+            };
+
+            let mut digits = String::new();
+            let mut in_parens = false;
+            for c in rest.chars() {
+                match c {
+                    '0'..='9' => digits.push(c),
+                    ' ' | '-' | '.' => {}
+                    '(' if !in_parens => in_parens = true,
+                    ')' if in_parens => in_parens = false,
+                    '(' | ')' => return Err(PhoneNumberError::UnbalancedParentheses),
+                    '+' => return Err(PhoneNumberError::MisplacedPlus),
+                    _ => return Err(PhoneNumberError::InvalidCharacter(c)),
+                }
+            }
+            if in_parens {
+                return Err(PhoneNumberError::UnbalancedParentheses);
+            }
+
+            // 没写 + 的 11 位号码，开头的 1 就是北美的国家码
+            let country_code = match country_code {
+                None if digits.len() == 11 && digits.starts_with('1') => {
+                    digits.remove(0);
+                    None
+                }
+                Some(DEFAULT_COUNTRY_CODE) => None,
+                other => other,
+            };
+            let valid_length = match country_code {
+                None => digits.len() == 7 || digits.len() == 10,
+                // E.164 规定包括国家码在内最多 15 位
+                Some(_) => (4..=12).contains(&digits.len()),
+            };
+            if !valid_length {
+                return Err(PhoneNumberError::InvalidLength(digits.len()));
+            }
+            Ok(PhoneNumber { country_code, national: digits })
+        }
+
+        pub fn country_code(&self) -> u16 {
+            self.country_code.unwrap_or(DEFAULT_COUNTRY_CODE)
+        }
+
+        // 北美号码的区号，本地号码没有区号
+        pub fn area_code(&self) -> Option<&str> {
+            match self.country_code {
+                None if self.national.len() == 10 => Some(&self.national[..3]),
+                _ => None,
+            }
+        }
+
+        pub fn digits(&self) -> &str {
+            &self.national
+        }
+    }
+
+    impl FromStr for PhoneNumber {
+        type Err = PhoneNumberError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            PhoneNumber::parse(s)
+        }
+    }
+
+    // 规范形式：798-1364、(555) 798-1364、+44 2079460958
+    impl fmt::Display for PhoneNumber {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let n = &self.national;
+            match self.country_code {
+                Some(code) => write!(f, "+{} {}", code, n),
+                None if n.len() == 10 => write!(f, "({}) {}-{}", &n[..3], &n[3..6], &n[6..]),
+                None => write!(f, "{}-{}", &n[..3], &n[3..]),
+            }
+        }
+    }
+}
+
+mod clock {
+    use std::cell::Cell;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // 时间来源，单位是 Unix 时间戳（秒）。需要时间的模块都通过它取时间，
+    // 这样演示和检查的时候可以换成 MockClock，结果是确定的
+    pub trait Clock {
+        fn now(&self) -> u64;
+    }
+
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> u64 {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+        }
+    }
+
+    // 手动拨动的时钟
+    pub struct MockClock {
+        now: Cell<u64>,
+    }
+
+    impl MockClock {
+        pub fn new(now: u64) -> MockClock {
+            MockClock { now: Cell::new(now) }
+        }
+
+        pub fn advance(&self, secs: u64) {
+            self.now.set(self.now.get() + secs);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> u64 {
+            self.now.get()
+        }
+    }
+
+    // 借用的时钟也是时钟，这样 MockClock 可以一边借给别人用，一边自己往前拨
+    impl<C: Clock + ?Sized> Clock for &C {
+        fn now(&self) -> u64 {
+            (**self).now()
+        }
+    }
+
+    // 格式化成 UTC 的 2023-02-05T16:18:35Z
+    pub fn format_timestamp(secs: u64) -> String {
+        let days = (secs / 86_400) as i64;
+        let rem = secs % 86_400;
+        // 按公历把天数换算成年月日（Howard Hinnant 的 civil_from_days 算法）
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60)
+    }
+}
+
+mod call_router {
+    use std::collections::{HashMap, HashSet};
+    use std::fmt;
+    use std::fs;
+    use std::path::Path;
+    use crate::clock::{format_timestamp, Clock};
+    use crate::phone_number::PhoneNumber;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Handler {
+        Busy,
+        // 语音信箱的主人
+        Voicemail(String),
+        Greeting(String),
+        Forward(PhoneNumber),
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum CallOutcome {
+        Busy,
+        Voicemail(String),
+        Answered(String),
+        // 转接成环，里面是环上的号码
+        ForwardingLoop(Vec<PhoneNumber>),
+        InvalidNumber(String),
+    }
+
+    impl fmt::Display for CallOutcome {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                CallOutcome::Busy => write!(f, "We're sorry, the call cannot be completed \
+                    Please hang up and try again."),
+                CallOutcome::Voicemail(owner) => write!(f, "You have reached the voicemail of {}. \
+                    Please leave a message after the tone.", owner),
+                CallOutcome::Answered(greeting) => write!(f, "{}", greeting),
+                CallOutcome::ForwardingLoop(numbers) => {
+                    let numbers: Vec<String> = numbers.iter().map(|n| n.to_string()).collect();
+                    write!(f, "Call forwarding loop: {}", numbers.join(" -> "))
+                }
+                CallOutcome::InvalidNumber(why) => write!(f, "The number you dialed is not valid: {}", why),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ConfigError {
+        pub line: usize,
+        pub reason: String,
+    }
+
+    impl fmt::Display for ConfigError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "line {}: {}", self.line, self.reason)
+        }
+    }
+
+    pub struct CallRouter {
+        routes: HashMap<PhoneNumber, Handler>,
+        // 没有配置的号码都走这里
+        default: Handler,
+    }
+
+    impl CallRouter {
+        // 配置每行一条路由：`号码 = 处理方式 [参数]`，# 开头是注释，号码写 * 表示默认路由。
+        // 处理方式有 busy、voicemail <主人>、greeting <问候语>、forward <号码>
+        pub fn from_config(config: &str) -> Result<CallRouter, ConfigError> {
+            let mut routes = HashMap::new();
+            let mut default = None;
+            for (index, line) in config.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let error = |reason: String| ConfigError { line: index + 1, reason };
+                let (number, handler) = line.split_once('=')
+                    .ok_or_else(|| error("expected `number = handler`".to_string()))?;
+                let handler = parse_handler(handler.trim()).map_err(error)?;
+                let number = number.trim();
+                if number == "*" {
+                    default = Some(handler);
+                    continue;
+                }
+                let number = PhoneNumber::parse(number).map_err(|e| error(e.to_string()))?;
+                if routes.insert(number, handler).is_some() {
+                    return Err(error("duplicate route".to_string()));
+                }
+            }
+            let default = default.ok_or(ConfigError { line: 0, reason: "missing default route `*`".to_string() })?;
+            Ok(CallRouter { routes, default })
+        }
+
+        pub fn load<P: AsRef<Path>>(path: P) -> Result<CallRouter, ConfigError> {
+            let config = fs::read_to_string(path)
+                .map_err(|e| ConfigError { line: 0, reason: e.to_string() })?;
+            CallRouter::from_config(&config)
+        }
+
+        // 返回一路经过的号码（第一个是拨出的号码）以及最终结果
+        pub fn route(&self, dialed: &str) -> (Vec<PhoneNumber>, CallOutcome) {
+            let mut number = match PhoneNumber::parse(dialed) {
+                Ok(number) => number,
+                Err(why) => return (Vec::new(), CallOutcome::InvalidNumber(why.to_string())),
+            };
+            let mut path = Vec::new();
+            let mut visited = HashSet::new();
+            loop {
+                // insert 返回 false 说明这个号码之前已经转接过，再转下去就是死循环
+                if !visited.insert(number.clone()) {
+                    let start = path.iter().position(|n| *n == number).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(number);
+                    return (path, CallOutcome::ForwardingLoop(cycle));
+                }
+                path.push(number.clone());
+                let outcome = match self.routes.get(&number).unwrap_or(&self.default) {
+                    Handler::Busy => CallOutcome::Busy,
+                    Handler::Voicemail(owner) => CallOutcome::Voicemail(owner.clone()),
+                    Handler::Greeting(greeting) => CallOutcome::Answered(greeting.clone()),
+                    Handler::Forward(next) => {
+                        number = next.clone();
+                        continue;
+                    }
+                };
+                return (path, outcome);
+            }
+        }
+    }
+
+    fn parse_handler(spec: &str) -> Result<Handler, String> {
+        let (kind, arg) = match spec.split_once(char::is_whitespace) {
+            Some((kind, arg)) => (kind, arg.trim()),
+            None => (spec, ""),
+        };
+        let require_arg = || if arg.is_empty() { Err(format!("`{}` needs an argument", kind)) } else { Ok(arg.to_string()) };
+        match kind {
+            "busy" if arg.is_empty() => Ok(Handler::Busy),
+            "voicemail" => require_arg().map(Handler::Voicemail),
+            "greeting" => require_arg().map(Handler::Greeting),
+            "forward" => PhoneNumber::parse(&require_arg()?).map(Handler::Forward).map_err(|e| e.to_string()),
+            _ => Err(format!("unknown handler {:?}", spec)),
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct CallRecord {
+        pub timestamp: u64,
+        pub dialed: String,
+        // 途经的号码，没有转接时只有拨出的号码本身
+        pub forwarded_via: Vec<PhoneNumber>,
+        pub outcome: CallOutcome,
+    }
+
+    impl fmt::Display for CallRecord {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "[{}] {}", format_timestamp(self.timestamp), self.dialed)?;
+            for number in self.forwarded_via.iter().skip(1) {
+                write!(f, " -> {}", number)?;
+            }
+            let summary = match &self.outcome {
+                CallOutcome::Busy => "busy".to_string(),
+                CallOutcome::Voicemail(owner) => format!("voicemail ({})", owner),
+                CallOutcome::Answered(_) => "answered".to_string(),
+                CallOutcome::ForwardingLoop(_) => "forwarding loop".to_string(),
+                CallOutcome::InvalidNumber(why) => format!("invalid number ({})", why),
+            };
+            write!(f, ": {}", summary)
+        }
+    }
+
+    // 通话记录：每次拨号都记下时间、号码和结果
+    pub struct CallLog<C: Clock> {
+        clock: C,
+        records: Vec<CallRecord>,
+    }
+
+    impl<C: Clock> CallLog<C> {
+        pub fn new(clock: C) -> Self {
+            CallLog { clock, records: Vec::new() }
+        }
+
+        pub fn call(&mut self, router: &CallRouter, number: &str) -> &CallRecord {
+            let (forwarded_via, outcome) = router.route(number);
+            self.records.push(CallRecord {
+                timestamp: self.clock.now(),
+                dialed: number.to_string(),
+                forwarded_via,
+                outcome,
+            });
+            self.records.last().unwrap()
+        }
+
+        pub fn records(&self) -> &[CallRecord] {
+            &self.records
+        }
+    }
+}
+
+mod contact_search {
+    use std::cmp::Ordering;
+    use crate::phone_book::PhoneBook;
+    use crate::phone_number::PhoneNumber;
+
+    // 匹配方式，排在前面的更相关
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum MatchKind {
+        // 忽略大小写后完全相同
+        Exact,
+        Prefix,
+        // 编辑距离（Levenshtein）
+        Fuzzy(usize),
+        // 查询的数字出现在号码里
+        Number,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct SearchHit<'a> {
+        pub name: &'a str,
+        pub number: &'a PhoneNumber,
+        pub kind: MatchKind,
+    }
+
+    // 编辑距离的上限随名字长度放宽一些：短名字只容许 1 处错误
+    fn max_distance(query_len: usize) -> usize {
+        (query_len / 4).max(1)
+    }
+
+    // 按相关度排序返回所有匹配的联系人
+    pub fn search<'a>(book: &'a PhoneBook, query: &str) -> Vec<SearchHit<'a>> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let lowered = query.to_lowercase();
+        // 只有数字和号码分隔符的查询按号码找
+        let digits: Option<String> = if query.chars().all(|c| c.is_ascii_digit() || " -.()+".contains(c)) {
+            Some(query.chars().filter(char::is_ascii_digit).collect())
+        } else {
+            None
+        };
+
+        let mut hits: Vec<SearchHit> = book.list()
+            .filter_map(|(name, number)| {
+                let kind = match &digits {
+                    Some(digits) if !digits.is_empty() => {
+                        if number.digits().contains(digits.as_str()) { Some(MatchKind::Number) } else { None }
+                    }
+                    Some(_) => None,
+                    None => match_name(&name.to_lowercase(), &lowered),
+                };
+                kind.map(|kind| SearchHit { name, number, kind })
+            })
+            .collect();
+        hits.sort_by(|a, b| match a.kind.cmp(&b.kind) {
+            Ordering::Equal => a.name.cmp(b.name),
+            other => other,
+        });
+        hits
+    }
+
+    fn match_name(name: &str, query: &str) -> Option<MatchKind> {
+        if name == query {
+            return Some(MatchKind::Exact);
+        }
+        if name.starts_with(query) {
+            return Some(MatchKind::Prefix);
+        }
+        let distance = levenshtein(name, query);
+        if distance <= max_distance(query.chars().count()) {
+            Some(MatchKind::Fuzzy(distance))
+        } else {
+            None
+        }
+    }
+
+    // 经典的动态规划，只保留一行
+    pub fn levenshtein(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, ca) in a.chars().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, cb) in b.iter().enumerate() {
+                let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+                diagonal = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+            }
+        }
+        row[b.len()]
+    }
+}
+
+mod credential {
+    use std::fmt;
+    use std::fs::File;
+    use std::io::Read;
+    use std::str::FromStr;
+    use std::sync::atomic::{compiler_fence, Ordering};
+
+    // 明文口令：离开作用域时把内存清零，Debug 也不会打印内容
+    pub struct Secret(Vec<u8>);
+
+    impl Secret {
+        pub fn expose(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    impl From<&str> for Secret {
+        fn from(plaintext: &str) -> Secret {
+            Secret(plaintext.as_bytes().to_vec())
+        }
+    }
+
+    // 取得 bytes 的所有权，不再另外复制一份
+    impl From<Vec<u8>> for Secret {
+        fn from(bytes: Vec<u8>) -> Secret {
+            Secret(bytes)
+        }
+    }
+
+    impl fmt::Debug for Secret {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "Secret(***)")
+        }
+    }
+
+    impl Drop for Secret {
+        fn drop(&mut self) {
+            zeroize(&mut self.0);
+        }
+    }
+
+    // 用 volatile 写清零，防止编译器认为“写了也没人读”而把它优化掉
+    pub fn zeroize<T: Default + Copy>(buf: &mut [T]) {
+        for x in buf.iter_mut() {
+            unsafe { std::ptr::write_volatile(x, T::default()) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    // 逐字节异或再累加，比较时间只跟长度有关，跟第几个字节不同无关
+    pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+        let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+        std::hint::black_box(diff) == 0
+    }
+
+    // 优先读 /dev/urandom；读不到时退回到 RandomState，它的种子同样来自操作系统的随机数
+    pub fn random_bytes(buf: &mut [u8]) {
+        if let Ok(mut urandom) = File::open("/dev/urandom") {
+            if urandom.read_exact(buf).is_ok() {
+                return;
+            }
+        }
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+        for (i, chunk) in buf.chunks_mut(8).enumerate() {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_usize(i);
+            chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    // scrypt 的代价参数：N = 2^log_n 决定内存和时间（内存约 128 * r * N 字节），p 是并行度
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct KdfParams {
+        pub log_n: u8,
+        pub r: u32,
+        pub p: u32,
+    }
+
+    // 参数的上限。存下来的散列可能被篡改或损坏，不加限制的话一次登录就可能要求分配几十 GB
+    const MAX_LOG_N: u8 = 20;
+    const MAX_P: u32 = 16;
+    const MAX_MEMORY: usize = 256 << 20;
+    // 总计算量 N * r * p 的上限，是 RECOMMENDED 的 16 倍
+    const MAX_WORK: u64 = 1 << 22;
+
+    impl KdfParams {
+        // 约 32 MiB，适合真正存口令
+        pub const RECOMMENDED: KdfParams = KdfParams { log_n: 15, r: 8, p: 1 };
+        // 约 1 MiB，只是为了让示例（尤其是 debug 构建）跑得快
+        pub const FAST: KdfParams = KdfParams { log_n: 10, r: 8, p: 1 };
+
+        // V 数组占用的字节数 128 * r * N，溢出时返回 None
+        pub fn memory(self) -> Option<usize> {
+            128usize.checked_mul(self.r as usize)?.checked_mul(1usize.checked_shl(self.log_n as u32)?)
+        }
+
+        // B 数组占用的字节数 128 * r * p，溢出时返回 None
+        fn buffer(self) -> Option<usize> {
+            128usize.checked_mul(self.r as usize)?.checked_mul(self.p as usize)
+        }
+
+        // RFC 7914 要求 r * p < 2^30；另外限制 N、p、两块内存和总计算量
+        pub fn is_supported(self) -> bool {
+            (1..=MAX_LOG_N).contains(&self.log_n)
+                && self.r >= 1
+                && (1..=MAX_P).contains(&self.p)
+                && (self.r as u64) * (self.p as u64) < 1 << 30
+                && self.memory().is_some_and(|memory| memory <= MAX_MEMORY)
+                && self.buffer().is_some_and(|buffer| buffer <= MAX_MEMORY)
+                && ((self.r as u64) * (self.p as u64)) << self.log_n <= MAX_WORK
+        }
+    }
+
+    impl Default for KdfParams {
+        fn default() -> Self {
+            KdfParams::RECOMMENDED
+        }
+    }
+
+    const SALT_LEN: usize = 16;
+    const HASH_LEN: usize = 32;
+
+    // 加盐的口令散列，可以序列化成 $scrypt$ln=15,r=8,p=1$<盐>$<散列> 这样的字符串保存
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct PasswordHash {
+        params: KdfParams,
+        salt: [u8; SALT_LEN],
+        hash: [u8; HASH_LEN],
+    }
+
+    impl PasswordHash {
+        // 每次都用新的随机盐，同一个口令两次散列的结果也不一样
+        pub fn new(password: &Secret, params: KdfParams) -> PasswordHash {
+            let mut salt = [0u8; SALT_LEN];
+            random_bytes(&mut salt);
+            let mut hash = [0u8; HASH_LEN];
+            scrypt(password.expose(), &salt, params, &mut hash);
+            PasswordHash { params, salt, hash }
+        }
+
+        pub fn verify(&self, password: &Secret) -> bool {
+            let mut candidate = [0u8; HASH_LEN];
+            scrypt(password.expose(), &self.salt, self.params, &mut candidate);
+            let matches = constant_time_eq(&candidate, &self.hash);
+            zeroize(&mut candidate);
+            matches
+        }
+    }
+
+    impl fmt::Display for PasswordHash {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "$scrypt$ln={},r={},p={}${}${}",
+                   self.params.log_n, self.params.r, self.params.p, hex(&self.salt), hex(&self.hash))
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct ParseHashError;
+
+    impl fmt::Display for ParseHashError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "malformed password hash")
+        }
+    }
+
+    impl FromStr for PasswordHash {
+        type Err = ParseHashError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let parts: Vec<&str> = s.split('$').collect();
+            let (params, salt, hash) = match parts.as_slice() {
+                ["", "scrypt", params, salt, hash] => (params, salt, hash),
+                _ => return Err(ParseHashError),
+            };
+            let mut log_n = None;
+            let mut r = None;
+            let mut p = None;
+            for param in params.split(',') {
+                match param.split_once('=') {
+                    Some(("ln", v)) => log_n = v.parse().ok(),
+                    Some(("r", v)) => r = v.parse().ok(),
+                    Some(("p", v)) => p = v.parse().ok(),
+                    _ => return Err(ParseHashError),
+                }
+            }
+            let params = match (log_n, r, p) {
+                (Some(log_n), Some(r), Some(p)) => KdfParams { log_n, r, p },
+                _ => return Err(ParseHashError),
+            };
+            if !params.is_supported() {
+                return Err(ParseHashError);
+            }
+            Ok(PasswordHash {
+                params,
+                salt: unhex(salt).ok_or(ParseHashError)?,
+                hash: unhex(hash).ok_or(ParseHashError)?,
+            })
+        }
+    }
+
+    pub fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+        if s.len() != N * 2 || !s.is_ascii() {
+            return None;
+        }
+        let mut out = [0u8; N];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(out)
+    }
+
+    // ---- SHA-256（FIPS 180-4）----
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+
+    #[derive(Clone)]
+    pub struct Sha256 {
+        state: [u32; 8],
+        buffer: [u8; 64],
+        buffered: usize,
+        length: u64,
+    }
+
+    impl Default for Sha256 {
+        fn default() -> Self {
+            Sha256::new()
+        }
+    }
+
+    impl Sha256 {
+        pub fn new() -> Sha256 {
+            Sha256 {
+                state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                        0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+                buffer: [0; 64],
+                buffered: 0,
+                length: 0,
+            }
+        }
+
+        pub fn digest(data: &[u8]) -> [u8; 32] {
+            let mut sha = Sha256::new();
+            sha.update(data);
+            sha.finish()
+        }
+
+        pub fn update(&mut self, mut data: &[u8]) {
+            self.length += data.len() as u64;
+            while !data.is_empty() {
+                let n = (64 - self.buffered).min(data.len());
+                self.buffer[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+                self.buffered += n;
+                data = &data[n..];
+                if self.buffered == 64 {
+                    let block = self.buffer;
+                    self.compress(&block);
+                    self.buffered = 0;
+                }
+            }
+        }
+
+        pub fn finish(mut self) -> [u8; 32] {
+            let bit_len = self.length * 8;
+            self.update(&[0x80]);
+            while self.buffered != 56 {
+                self.update(&[0]);
+            }
+            self.update(&bit_len.to_be_bytes());
+            let mut out = [0u8; 32];
+            for (chunk, word) in out.chunks_mut(4).zip(self.state.iter()) {
+                chunk.copy_from_slice(&word.to_be_bytes());
+            }
+            out
+        }
+
+        fn compress(&mut self, block: &[u8; 64]) {
+            let mut w = [0u32; 64];
+            for (i, chunk) in block.chunks(4).enumerate() {
+                w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
+            for i in 16..64 {
+                let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+                let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+                w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+            }
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+            for i in 0..64 {
+                let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+                let ch = (e & f) ^ (!e & g);
+                let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+                let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+                let maj = (a & b) ^ (a & c) ^ (b & c);
+                let t2 = s0.wrapping_add(maj);
+                h = g;
+                g = f;
+                f = e;
+                e = d.wrapping_add(t1);
+                d = c;
+                c = b;
+                b = a;
+                a = t1.wrapping_add(t2);
+            }
+            for (state, x) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+                *state = state.wrapping_add(x);
+            }
+        }
+    }
+
+    // ---- HMAC-SHA256（RFC 2104）----
+    #[derive(Clone)]
+    pub struct HmacSha256 {
+        inner: Sha256,
+        outer: Sha256,
+    }
+
+    // 密钥比块长就先散列一次，再补 0 到块长
+    fn hmac_key_block<const B: usize>(key: &[u8], hash: impl FnOnce(&[u8]) -> Vec<u8>) -> [u8; B] {
+        let mut block = [0u8; B];
+        if key.len() > B {
+            let digest = hash(key);
+            block[..digest.len()].copy_from_slice(&digest);
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        block
+    }
+
+    impl HmacSha256 {
+        pub fn new(key: &[u8]) -> HmacSha256 {
+            let mut block = hmac_key_block::<64>(key, |key| Sha256::digest(key).to_vec());
+            let mut inner = Sha256::new();
+            let mut outer = Sha256::new();
+            inner.update(&block.map(|b| b ^ 0x36));
+            outer.update(&block.map(|b| b ^ 0x5c));
+            zeroize(&mut block);
+            HmacSha256 { inner, outer }
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.inner.update(data);
+        }
+
+        pub fn finish(self) -> [u8; 32] {
+            let mut outer = self.outer;
+            outer.update(&self.inner.finish());
+            outer.finish()
+        }
+    }
+
+    // ---- SHA-1 和 SHA-512：只给 HMAC-based OTP 用（RFC 6238 规定了这三种算法），不要拿 SHA-1 存口令 ----
+    // 消息末尾补 0x80、若干 0 和消息长度，凑成整块
+    fn pad(data: &[u8], block_len: usize, length_len: usize) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        padded.push(0x80);
+        while !(padded.len() + length_len).is_multiple_of(block_len) {
+            padded.push(0);
+        }
+        let bit_len = (data.len() as u128) * 8;
+        padded.extend_from_slice(&bit_len.to_be_bytes()[16 - length_len..]);
+        padded
+    }
+
+    pub fn sha1(data: &[u8]) -> [u8; 20] {
+        let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+        for block in pad(data, 64, 8).chunks(64) {
+            let mut w = [0u32; 80];
+            for (i, chunk) in block.chunks(4).enumerate() {
+                w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
+            for i in 16..80 {
+                w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+            }
+            let [mut a, mut b, mut c, mut d, mut e] = state;
+            for (i, w) in w.iter().enumerate() {
+                let (f, k) = match i {
+                    0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                    20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                    40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                    _ => (b ^ c ^ d, 0xca62c1d6),
+                };
+                let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*w);
+                e = d;
+                d = c;
+                c = b.rotate_left(30);
+                b = a;
+                a = t;
+            }
+            for (state, x) in state.iter_mut().zip([a, b, c, d, e]) {
+                *state = state.wrapping_add(x);
+            }
+        }
+        let mut out = [0u8; 20];
+        for (chunk, word) in out.chunks_mut(4).zip(state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    // 一次算完的 HMAC，给只有一次性接口的 SHA-1 和 SHA-512 用
+    fn hmac<const B: usize, const N: usize>(hash: fn(&[u8]) -> [u8; N], key: &[u8], message: &[u8]) -> [u8; N] {
+        let mut block = hmac_key_block::<B>(key, |key| hash(key).to_vec());
+        let mut inner = block.map(|b| b ^ 0x36).to_vec();
+        inner.extend_from_slice(message);
+        let mut outer = block.map(|b| b ^ 0x5c).to_vec();
+        zeroize(&mut block);
+        outer.extend_from_slice(&hash(&inner));
+        zeroize(&mut inner);
+        let mac = hash(&outer);
+        zeroize(&mut outer);
+        mac
+    }
+
+    pub fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+        hmac::<64, 20>(sha1, key, message)
+    }
+
+    pub fn hmac_sha512(key: &[u8], message: &[u8]) -> [u8; 64] {
+        hmac::<128, 64>(sha512, key, message)
+    }
+
+    const K512: [u64; 80] = [
+        0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+        0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+        0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+        0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+        0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+        0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+        0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+        0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+        0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+        0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+        0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+        0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+        0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+        0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+        0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+        0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+        0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+        0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+        0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+        0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+    ];
+
+    pub fn sha512(data: &[u8]) -> [u8; 64] {
+        let mut state: [u64; 8] = [
+            0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+            0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+        ];
+        for block in pad(data, 128, 16).chunks(128) {
+            let mut w = [0u64; 80];
+            for (i, chunk) in block.chunks(8).enumerate() {
+                w[i] = u64::from_be_bytes(chunk.try_into().unwrap());
+            }
+            for i in 16..80 {
+                let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+                let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+                w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+            }
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+            for i in 0..80 {
+                let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+                let ch = (e & f) ^ (!e & g);
+                let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K512[i]).wrapping_add(w[i]);
+                let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+                let maj = (a & b) ^ (a & c) ^ (b & c);
+                let t2 = s0.wrapping_add(maj);
+                h = g;
+                g = f;
+                f = e;
+                e = d.wrapping_add(t1);
+                d = c;
+                c = b;
+                b = a;
+                a = t1.wrapping_add(t2);
+            }
+            for (state, x) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+                *state = state.wrapping_add(x);
+            }
+        }
+        let mut out = [0u8; 64];
+        for (chunk, word) in out.chunks_mut(8).zip(state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    // ---- PBKDF2-HMAC-SHA256（RFC 8018）----
+    pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], rounds: u32, out: &mut [u8]) {
+        let prf = HmacSha256::new(password);
+        for (i, chunk) in out.chunks_mut(32).enumerate() {
+            let mut mac = prf.clone();
+            mac.update(salt);
+            mac.update(&(i as u32 + 1).to_be_bytes());
+            let mut u = mac.finish();
+            let mut t = u;
+            for _ in 1..rounds {
+                let mut mac = prf.clone();
+                mac.update(&u);
+                u = mac.finish();
+                for (t, u) in t.iter_mut().zip(u.iter()) {
+                    *t ^= u;
+                }
+            }
+            chunk.copy_from_slice(&t[..chunk.len()]);
+        }
+    }
+
+    // ---- scrypt（RFC 7914）：内存困难的 KDF，暴力破解需要同时付出大量内存 ----
+    // 参数必须通过 KdfParams::is_supported，否则 panic（从文件读入的参数在解析时就检查过了）
+    pub fn scrypt(password: &[u8], salt: &[u8], params: KdfParams, out: &mut [u8]) {
+        assert!(params.is_supported(), "unsupported scrypt parameters {:?}", params);
+        let size = |a: usize, b: usize| a.checked_mul(b).expect("scrypt parameters overflow");
+        let r = params.r as usize;
+        let n = 1usize << params.log_n;
+        let block_words = size(32, r);
+        let mut b = vec![0u8; size(size(128, r), params.p as usize)];
+        pbkdf2_sha256(password, salt, 1, &mut b);
+        let mut x = vec![0u32; block_words];
+        let mut v = vec![0u32; size(block_words, n)];
+        let mut scratch = vec![0u32; block_words];
+        for chunk in b.chunks_mut(128 * r) {
+            for (word, bytes) in x.iter_mut().zip(chunk.chunks(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            ro_mix(&mut x, &mut v, &mut scratch, n);
+            for (word, bytes) in x.iter().zip(chunk.chunks_mut(4)) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+        }
+        pbkdf2_sha256(password, &b, 1, out);
+        zeroize(&mut b);
+        zeroize(&mut x);
+        zeroize(&mut v);
+        zeroize(&mut scratch);
+    }
+
+    fn ro_mix(x: &mut [u32], v: &mut [u32], scratch: &mut [u32], n: usize) {
+        let len = x.len();
+        for i in 0..n {
+            v[i * len..(i + 1) * len].copy_from_slice(x);
+            block_mix(x, scratch);
+        }
+        for _ in 0..n {
+            // Integerify：取最后一个 64 字节块的第一个字
+            let j = x[len - 16] as usize & (n - 1);
+            for (x, v) in x.iter_mut().zip(&v[j * len..(j + 1) * len]) {
+                *x ^= v;
+            }
+            block_mix(x, scratch);
+        }
+    }
+
+    fn block_mix(b: &mut [u32], y: &mut [u32]) {
+        let blocks = b.len() / 16;
+        let mut x = [0u32; 16];
+        x.copy_from_slice(&b[(blocks - 1) * 16..]);
+        for i in 0..blocks {
+            for (x, b) in x.iter_mut().zip(&b[i * 16..(i + 1) * 16]) {
+                *x ^= b;
+            }
+            salsa20_8(&mut x);
+            // 偶数块放前半部分，奇数块放后半部分
+            let dest = (i / 2 + (i % 2) * (blocks / 2)) * 16;
+            y[dest..dest + 16].copy_from_slice(&x);
+        }
+        b.copy_from_slice(y);
+    }
+
+    fn salsa20_8(block: &mut [u32; 16]) {
+        let mut x = *block;
+        for _ in 0..4 {
+            for &(a, b, c, d) in &[(0, 4, 8, 12), (5, 9, 13, 1), (10, 14, 2, 6), (15, 3, 7, 11),
+                                   (0, 1, 2, 3), (5, 6, 7, 4), (10, 11, 8, 9), (15, 12, 13, 14)] {
+                x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+                x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+                x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+                x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+            }
+        }
+        for (block, x) in block.iter_mut().zip(x.iter()) {
+            *block = block.wrapping_add(*x);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn same_password_gets_a_new_salt_and_hash_strings_parse_back() {
+            let first = PasswordHash::new(&Secret::from("password123"), KdfParams::FAST);
+            let second = PasswordHash::new(&Secret::from("password123"), KdfParams::FAST);
+            assert_ne!(first, second);
+            assert_eq!(first.to_string().parse::<PasswordHash>(), Ok(first.clone()));
+            assert!(first.verify(&Secret::from("password123")));
+            assert!(!first.verify(&Secret::from("password124")));
+            assert!("$scrypt$ln=10$00$00".parse::<PasswordHash>().is_err());
+        }
+
+        // 篡改过的散列字符串不能让验证去分配巨量内存或溢出：超出上限的参数在解析时就被拒绝
+        #[test]
+        fn tampered_parameters_are_rejected_when_parsing() {
+            let hash = PasswordHash::new(&Secret::from("password123"), KdfParams::FAST).to_string();
+            let tampered = |params: &str| hash.replacen("ln=10,r=8,p=1", params, 1).parse::<PasswordHash>();
+            assert!(tampered("ln=10,r=8,p=1").is_ok());
+            for params in ["ln=31,r=8,p=1", "ln=21,r=1,p=1", "ln=20,r=4294967295,p=1", "ln=10,r=65536,p=65536", "ln=0,r=8,p=1",
+                           "ln=10,r=0,p=1", "ln=1,r=1,p=536870911", "ln=1,r=1,p=17", "ln=16,r=8,p=16"] {
+                assert!(tampered(params).is_err(), "{} should be rejected", params);
+            }
+            assert!(KdfParams::RECOMMENDED.is_supported());
+            assert_eq!(KdfParams { log_n: 20, r: 8, p: 1 }.memory(), Some(1 << 30));
+        }
+
+        #[test]
+        fn secrets_are_not_printed_and_comparison_is_exact() {
+            assert!(constant_time_eq(b"abc", b"abc") && !constant_time_eq(b"abc", b"abd"));
+            assert!(!constant_time_eq(b"abc", b"abcd"));
+            assert_eq!(format!("{:?}", Secret::from("password123")), "Secret(***)");
+        }
+
+        // FIPS 180-2 和 RFC 7914 第 12 节的测试向量
+        #[test]
+        fn sha256_and_scrypt_match_the_test_vectors() {
+            assert_eq!(hex(&Sha256::digest(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+            let mut derived = [0u8; 64];
+            scrypt(b"", b"", KdfParams { log_n: 4, r: 1, p: 1 }, &mut derived);
+            assert_eq!(hex(&derived), "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+                                       fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906");
+            scrypt(b"password", b"NaCl", KdfParams { log_n: 10, r: 8, p: 16 }, &mut derived);
+            assert_eq!(hex(&derived), "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+                                       2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640");
+        }
+    }
+}
+
+mod accounts {
+    use std::borrow::Borrow;
+    use std::cell::OnceCell;
+    use std::collections::HashMap;
+    use std::fmt;
+    use crate::credential::{random_bytes, KdfParams, PasswordHash, Secret};
+    use crate::hashers::{HashState, HasherKind};
+    use crate::phone_number::PhoneNumber;
+
+    const MIN_PASSWORD_LEN: usize = 8;
+
+    // 散列表的键只有用户名（统一成小写，所以 Jane 和 jane 是同一个账号）。
+    // 实现了 Borrow<str>，查找时可以直接传 &str，不用先构造一个 Account
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct Account {
+        username: String,
+    }
+
+    impl Account {
+        pub fn username(&self) -> &str {
+            &self.username
+        }
+    }
+
+    impl Borrow<str> for Account {
+        fn borrow(&self) -> &str {
+            &self.username
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AccountInfo {
+        pub name: String,
+        pub email: String,
+        // 账号数据库第 2 版加的字段，旧数据迁移过来时为 None
+        pub phone: Option<PhoneNumber>,
+    }
+
+    impl AccountInfo {
+        pub fn new(name: &str, email: &str) -> AccountInfo {
+            AccountInfo { name: name.to_string(), email: email.to_string(), phone: None }
+        }
+
+        pub fn with_phone(mut self, phone: PhoneNumber) -> AccountInfo {
+            self.phone = Some(phone);
+            self
+        }
+    }
+
+    pub struct AccountRecord {
+        pub credential: PasswordHash,
+        pub info: AccountInfo,
+    }
+
+    pub type Accounts = HashMap<Account, AccountRecord, HashState>;
+
+    #[derive(Debug, PartialEq)]
+    pub enum AccountError {
+        InvalidUsername(&'static str),
+        InvalidEmail,
+        WeakPassword,
+        UsernameTaken,
+        EmailTaken,
+        NotFound,
+        WrongPassword,
+    }
+
+    impl fmt::Display for AccountError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                AccountError::InvalidUsername(why) => write!(f, "invalid username: {}", why),
+                AccountError::InvalidEmail => write!(f, "invalid email address"),
+                AccountError::WeakPassword => write!(f, "password must be at least {} characters", MIN_PASSWORD_LEN),
+                AccountError::UsernameTaken => write!(f, "username is already taken"),
+                AccountError::EmailTaken => write!(f, "email is already registered"),
+                AccountError::NotFound => write!(f, "no such account"),
+                AccountError::WrongPassword => write!(f, "wrong password"),
+            }
+        }
+    }
+
+    // 3 到 32 个字符，字母开头，只能包含字母、数字和 . _ -
+    fn validate_username(username: &str) -> Result<String, AccountError> {
+        if !(3..=32).contains(&username.len()) {
+            return Err(AccountError::InvalidUsername("must be 3 to 32 characters"));
+        }
+        if !username.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(AccountError::InvalidUsername("must start with a letter"));
+        }
+        if !username.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+            return Err(AccountError::InvalidUsername("only letters, digits, '.', '_' and '-' are allowed"));
+        }
+        Ok(username.to_ascii_lowercase())
+    }
+
+    // 不追求完整的 RFC 5322，只拦住明显不对的：恰好一个 @，域名里要有点，不能有空白
+    fn validate_email(email: &str) -> Result<(), AccountError> {
+        let valid = match email.split_once('@') {
+            Some((local, domain)) => !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() >= 2
+                && domain.split('.').all(|label| !label.is_empty())
+                && !email.contains(char::is_whitespace),
+            None => false,
+        };
+        if valid { Ok(()) } else { Err(AccountError::InvalidEmail) }
+    }
+
+    fn validate_password(password: &Secret) -> Result<(), AccountError> {
+        let len = String::from_utf8_lossy(password.expose()).chars().count();
+        if len < MIN_PASSWORD_LEN { Err(AccountError::WeakPassword) } else { Ok(()) }
+    }
+
+    pub struct AccountStore {
+        accounts: Accounts,
+        params: KdfParams,
+        // 用户名不存在时拿来核对的散列，让这种情况和口令错误花的时间一样，
+        // 不能靠响应时间探测用户名是否存在。第一次用到时才生成
+        dummy: OnceCell<PasswordHash>,
+    }
+
+    impl Default for AccountStore {
+        fn default() -> Self {
+            AccountStore::new()
+        }
+    }
+
+    impl AccountStore {
+        pub fn new() -> AccountStore {
+            AccountStore::with_params(KdfParams::default())
+        }
+
+        // 新口令用 params 散列
+        pub fn with_params(params: KdfParams) -> AccountStore {
+            AccountStore::with_hasher(params, HasherKind::default())
+        }
+
+        // 用户名是外部输入，除了基准测试不要换掉默认的 SipHash
+        pub fn with_hasher(params: KdfParams, kind: HasherKind) -> AccountStore {
+            AccountStore { accounts: HashMap::with_hasher(HashState::new(kind)), params, dummy: OnceCell::new() }
+        }
+
+        pub fn len(&self) -> usize {
+            self.accounts.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.accounts.is_empty()
+        }
+
+        fn email_taken(&self, email: &str, except: Option<&str>) -> bool {
+            self.accounts.iter().any(|(account, record)| {
+                Some(account.username()) != except && record.info.email.eq_ignore_ascii_case(email)
+            })
+        }
+
+        pub fn register(&mut self, username: &str, password: Secret, info: AccountInfo) -> Result<(), AccountError> {
+            let username = validate_username(username)?;
+            validate_email(&info.email)?;
+            validate_password(&password)?;
+            if self.accounts.contains_key(username.as_str()) {
+                return Err(AccountError::UsernameTaken);
+            }
+            if self.email_taken(&info.email, None) {
+                return Err(AccountError::EmailTaken);
+            }
+            let credential = PasswordHash::new(&password, self.params);
+            self.accounts.insert(Account { username }, AccountRecord { credential, info });
+            Ok(())
+        }
+
+        pub fn get(&self, username: &str) -> Option<&AccountInfo> {
+            self.accounts.get(username.to_ascii_lowercase().as_str()).map(|record| &record.info)
+        }
+
+        // 核对口令，对了就返回账号资料。用户名不存在时也照样算一次散列
+        pub fn verify_password(&self, username: &str, password: &Secret) -> Result<&AccountInfo, AccountError> {
+            let record = match self.accounts.get(username.to_ascii_lowercase().as_str()) {
+                Some(record) => record,
+                None => {
+                    self.dummy_hash().verify(password);
+                    return Err(AccountError::NotFound);
+                }
+            };
+            if record.credential.verify(password) {
+                Ok(&record.info)
+            } else {
+                Err(AccountError::WrongPassword)
+            }
+        }
+
+        // 随机口令的散列，参数和新口令一样，所以核对一次的代价也一样
+        fn dummy_hash(&self) -> &PasswordHash {
+            self.dummy.get_or_init(|| {
+                let mut password = vec![0u8; 32];
+                random_bytes(&mut password);
+                PasswordHash::new(&Secret::from(password), self.params)
+            })
+        }
+
+        pub fn change_password(&mut self, username: &str, old: &Secret, new: Secret) -> Result<(), AccountError> {
+            self.verify_password(username, old)?;
+            validate_password(&new)?;
+            let credential = PasswordHash::new(&new, self.params);
+            let record = self.accounts.get_mut(username.to_ascii_lowercase().as_str()).ok_or(AccountError::NotFound)?;
+            record.credential = credential;
+            Ok(())
+        }
+
+        pub fn update_info(&mut self, username: &str, info: AccountInfo) -> Result<(), AccountError> {
+            let username = username.to_ascii_lowercase();
+            if !self.accounts.contains_key(username.as_str()) {
+                return Err(AccountError::NotFound);
+            }
+            validate_email(&info.email)?;
+            if self.email_taken(&info.email, Some(&username)) {
+                return Err(AccountError::EmailTaken);
+            }
+            self.accounts.get_mut(username.as_str()).ok_or(AccountError::NotFound)?.info = info;
+            Ok(())
+        }
+
+        // 删除账号，返回它的资料。只删账号本身，要连同会话、角色等一起清理用 account_lifecycle::delete
+        pub fn delete(&mut self, username: &str) -> Result<AccountInfo, AccountError> {
+            self.accounts.remove(username.to_ascii_lowercase().as_str())
+                .map(|record| record.info)
+                .ok_or(AccountError::NotFound)
+        }
+
+        // 直接放入一条已有的记录（比如从文件加载的），口令散列原样保留
+        pub fn insert_record(&mut self, username: &str, record: AccountRecord) -> Result<(), AccountError> {
+            let username = validate_username(username)?;
+            if self.accounts.contains_key(username.as_str()) {
+                return Err(AccountError::UsernameTaken);
+            }
+            self.accounts.insert(Account { username }, record);
+            Ok(())
+        }
+
+        // 按用户名排序，包括口令散列
+        pub fn records(&self) -> Vec<(&str, &AccountRecord)> {
+            let mut records: Vec<(&str, &AccountRecord)> = self.accounts.iter()
+                .map(|(account, record)| (account.username(), record))
+                .collect();
+            records.sort_by_key(|(username, _)| *username);
+            records
+        }
+
+        // 按用户名排序
+        pub fn list(&self) -> Vec<(&str, &AccountInfo)> {
+            let mut list: Vec<(&str, &AccountInfo)> = self.accounts.iter()
+                .map(|(account, record)| (account.username(), &record.info))
+                .collect();
+            list.sort_by_key(|(username, _)| *username);
+            list
+        }
+    }
+}
+
+mod login {
+    use std::collections::{HashMap, VecDeque};
+    use std::fmt;
+    use crate::accounts::{AccountInfo, AccountStore};
+    use crate::audit::{AuditEvent, AuditOutcome, AuditSink};
+    use crate::clock::Clock;
+    use crate::credential::Secret;
+    use crate::totp::TotpStore;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum LoginError {
+        // 用户名不存在或口令不对，两者故意不区分
+        InvalidCredentials,
+        // 还在退避时间内，retry_after 秒后再试
+        Throttled { retry_after: u64 },
+        // 失败次数太多，账号被临时锁定
+        LockedOut { retry_after: u64 },
+        // 口令正确，但账号启用了两步验证，还需要验证码
+        SecondFactorRequired,
+        InvalidSecondFactor,
+    }
+
+    impl fmt::Display for LoginError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                LoginError::InvalidCredentials => write!(f, "invalid username or password"),
+                LoginError::Throttled { retry_after } => write!(f, "too many attempts, retry in {}s", retry_after),
+                LoginError::LockedOut { retry_after } => write!(f, "account locked, retry in {}s", retry_after),
+                LoginError::SecondFactorRequired => write!(f, "verification code required"),
+                LoginError::InvalidSecondFactor => write!(f, "invalid verification code"),
+            }
+        }
+    }
+
+    impl LoginError {
+        // 审计日志里用的原因代码
+        pub fn reason(&self) -> &'static str {
+            match self {
+                LoginError::InvalidCredentials => "invalid_credentials",
+                LoginError::Throttled { .. } => "throttled",
+                LoginError::LockedOut { .. } => "locked_out",
+                LoginError::SecondFactorRequired => "second_factor_required",
+                LoginError::InvalidSecondFactor => "invalid_second_factor",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct ThrottlePolicy {
+        // 第 n 次失败之后要等 base_delay * 2^(n-1) 秒，最多等 max_delay 秒
+        pub base_delay: u64,
+        pub max_delay: u64,
+        // 连续失败这么多次就锁定 lockout_duration 秒
+        pub lockout_threshold: u32,
+        pub lockout_duration: u64,
+        // 任意 global_window 秒内，所有用户名的失败加起来最多 global_limit 次
+        pub global_window: u64,
+        pub global_limit: usize,
+        // 最后一次失败之后这么多秒（且不在锁定期内）就忘掉这个用户名的失败记录
+        pub forget_after: u64,
+    }
+
+    impl Default for ThrottlePolicy {
+        fn default() -> Self {
+            ThrottlePolicy {
+                base_delay: 1,
+                max_delay: 300,
+                lockout_threshold: 5,
+                lockout_duration: 900,
+                global_window: 60,
+                global_limit: 100,
+                forget_after: 900,
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Attempts {
+        failures: u32,
+        last_failure: u64,
+        // 在这个时间之前不接受新的尝试
+        next_allowed: u64,
+        locked_until: u64,
+    }
+
+    impl Attempts {
+        fn expired(&self, now: u64, policy: &ThrottlePolicy) -> bool {
+            self.locked_until <= now && self.last_failure.saturating_add(policy.forget_after) <= now
+        }
+    }
+
+    // 失败记录达到这么多条时清理一次过期的，之后的阈值是清理后剩余条数的两倍。
+    // 用随机用户名不停地失败也不会让表无限增长：全局限流限制了单位时间内的新条目数，过期的会被清掉
+    const MIN_PRUNE_AT: usize = 1024;
+
+    pub struct LoginThrottle<C: Clock> {
+        clock: C,
+        policy: ThrottlePolicy,
+        per_user: HashMap<String, Attempts>,
+        prune_at: usize,
+        // 窗口内每次失败的时间，最早的在前面
+        global_failures: VecDeque<u64>,
+    }
+
+    impl<C: Clock> LoginThrottle<C> {
+        pub fn new(clock: C, policy: ThrottlePolicy) -> Self {
+            LoginThrottle { clock, policy, per_user: HashMap::new(), prune_at: MIN_PRUNE_AT, global_failures: VecDeque::new() }
+        }
+
+        // 当前记着失败记录的用户名个数
+        pub fn tracked_users(&self) -> usize {
+            self.per_user.len()
+        }
+
+        // 用户名不区分大小写，和 AccountStore 一致
+        fn key(username: &str) -> String {
+            username.to_ascii_lowercase()
+        }
+
+        pub fn failures(&self, username: &str) -> u32 {
+            self.per_user.get(&Self::key(username)).map_or(0, |attempts| attempts.failures)
+        }
+
+        // 现在能不能尝试登录
+        pub fn check(&mut self, username: &str) -> Result<(), LoginError> {
+            let now = self.clock.now();
+            let window = self.policy.global_window;
+            while self.global_failures.front().is_some_and(|&t| t + window <= now) {
+                self.global_failures.pop_front();
+            }
+            if let Some(attempts) = self.per_user.get(&Self::key(username)) {
+                if attempts.locked_until > now {
+                    return Err(LoginError::LockedOut { retry_after: attempts.locked_until - now });
+                }
+                if attempts.next_allowed > now {
+                    return Err(LoginError::Throttled { retry_after: attempts.next_allowed - now });
+                }
+            }
+            if self.global_failures.len() >= self.policy.global_limit {
+                let oldest = self.global_failures[0];
+                return Err(LoginError::Throttled { retry_after: oldest + window - now });
+            }
+            Ok(())
+        }
+
+        pub fn record_failure(&mut self, username: &str) {
+            let now = self.clock.now();
+            let policy = self.policy;
+            self.global_failures.push_back(now);
+            if self.per_user.len() >= self.prune_at {
+                self.per_user.retain(|_, attempts| !attempts.expired(now, &policy));
+                self.prune_at = (self.per_user.len() * 2).max(MIN_PRUNE_AT);
+            }
+            let attempts = self.per_user.entry(Self::key(username)).or_default();
+            // 锁定期已经过去的话重新计数，很久没有失败过的也重新计数
+            if (attempts.locked_until != 0 && attempts.locked_until <= now) || attempts.expired(now, &policy) {
+                *attempts = Attempts::default();
+            }
+            attempts.failures += 1;
+            attempts.last_failure = now;
+            if attempts.failures >= policy.lockout_threshold {
+                attempts.locked_until = now + policy.lockout_duration;
+            } else {
+                let delay = policy.base_delay.saturating_mul(1 << (attempts.failures - 1).min(32));
+                attempts.next_allowed = now + delay.min(policy.max_delay);
+            }
+        }
+
+        pub fn record_success(&mut self, username: &str) {
+            self.per_user.remove(&Self::key(username));
+        }
+    }
+
+    // 先问 throttle 能不能试，再核对口令，并把结果记回 throttle。
+    // 每次尝试（包括被限流拦下的）都会写一条审计事件；写审计失败不影响登录结果，只打印到 stderr
+    pub fn try_logon<'a, C: Clock>(accounts: &'a AccountStore, throttle: &mut LoginThrottle<C>, audit: &mut dyn AuditSink,
+                                   username: &str, password: &Secret) -> Result<&'a AccountInfo, LoginError> {
+        authenticate(accounts, throttle, audit, username, password, || Ok(()))
+    }
+
+    // 启用了两步验证的账号，口令正确之后还要一个验证码（或恢复码）。
+    // 没带验证码时返回 SecondFactorRequired，调用方应该提示用户输入再重试；验证码错误和口令错误一样计入失败次数
+    pub fn try_logon_with_totp<'a, C: Clock, T: Clock>(accounts: &'a AccountStore, throttle: &mut LoginThrottle<C>,
+                                                       audit: &mut dyn AuditSink, totp: &mut TotpStore<T>,
+                                                       username: &str, password: &Secret,
+                                                       code: Option<&str>) -> Result<&'a AccountInfo, LoginError> {
+        authenticate(accounts, throttle, audit, username, password, || {
+            if !totp.is_enabled(username) {
+                return Ok(());
+            }
+            let code = code.ok_or(LoginError::SecondFactorRequired)?;
+            totp.verify(username, code).map_err(|_| LoginError::InvalidSecondFactor)
+        })
+    }
+
+    fn authenticate<'a, C: Clock, F>(accounts: &'a AccountStore, throttle: &mut LoginThrottle<C>, audit: &mut dyn AuditSink,
+                                     username: &str, password: &Secret, second_factor: F) -> Result<&'a AccountInfo, LoginError>
+        where F: FnOnce() -> Result<(), LoginError>
+    {
+        let result = throttle.check(username).and_then(|_| {
+            let info = accounts.verify_password(username, password).map_err(|_| LoginError::InvalidCredentials)?;
+            second_factor()?;
+            Ok(info)
+        });
+        match &result {
+            Ok(_) => throttle.record_success(username),
+            Err(LoginError::InvalidCredentials) | Err(LoginError::InvalidSecondFactor) => throttle.record_failure(username),
+            Err(_) => {}
+        }
+        let event = AuditEvent {
+            timestamp: throttle.clock.now(),
+            username: username.to_string(),
+            outcome: if result.is_ok() { AuditOutcome::Success } else { AuditOutcome::Failure },
+            reason: result.as_ref().err().map(LoginError::reason),
+        };
+        if let Err(why) = audit.record(&event) {
+            eprintln!("failed to write audit event: {}", why);
+        }
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::accounts::AccountInfo;
+        use crate::audit::MemorySink;
+        use crate::clock::MockClock;
+        use crate::credential::KdfParams;
+
+        fn accounts() -> AccountStore {
+            let mut accounts = AccountStore::with_params(KdfParams::FAST);
+            accounts.register("j.everyman", Secret::from("password123"), AccountInfo::new("John Everyman", "j.everyman@gmail.com"))
+                .unwrap();
+            accounts
+        }
+
+        fn attempt<C: Clock>(accounts: &AccountStore, throttle: &mut LoginThrottle<C>, audit: &mut MemorySink,
+                             username: &str, password: &str) -> Result<(), LoginError> {
+            try_logon(accounts, throttle, audit, username, &Secret::from(password)).map(|_| ())
+        }
+
+        // 默认策略下第 n 次失败后要等 2^(n-1) 秒，失败 5 次锁定 15 分钟
+        #[test]
+        fn backoff_doubles_and_five_failures_lock_the_account() {
+            let accounts = accounts();
+            let clock = MockClock::new(1_675_584_000);
+            let mut throttle = LoginThrottle::new(&clock, ThrottlePolicy::default());
+            let mut audit = MemorySink::default();
+            let mut attempt = |throttle: &mut LoginThrottle<_>, password| attempt(&accounts, throttle, &mut audit, "j.everyman", password);
+            assert_eq!(attempt(&mut throttle, "wrong"), Err(LoginError::InvalidCredentials));
+            // 还在退避时间内，连口令都不会去核对
+            assert_eq!(attempt(&mut throttle, "password123"), Err(LoginError::Throttled { retry_after: 1 }));
+            clock.advance(1);
+            assert_eq!(attempt(&mut throttle, "wrong"), Err(LoginError::InvalidCredentials));
+            assert_eq!(attempt(&mut throttle, "wrong"), Err(LoginError::Throttled { retry_after: 2 }));
+            for wait in [2, 4, 8] {
+                clock.advance(wait);
+                assert_eq!(attempt(&mut throttle, "wrong"), Err(LoginError::InvalidCredentials));
+            }
+            // 第 5 次失败之后，即使口令正确也登录不了
+            assert_eq!(attempt(&mut throttle, "password123"), Err(LoginError::LockedOut { retry_after: 900 }));
+            clock.advance(600);
+            assert_eq!(attempt(&mut throttle, "password123"), Err(LoginError::LockedOut { retry_after: 300 }));
+            clock.advance(300);
+            // 锁定期过后登录成功，失败记录清零
+            assert_eq!(attempt(&mut throttle, "password123"), Ok(()));
+            assert_eq!(throttle.failures("j.everyman"), 0);
+
+            // 审计事件里有时间、用户名、结果和原因
+            assert_eq!(audit.events.iter().filter(|event| event.reason == Some("locked_out")).count(), 2);
+            let last = audit.events.last().unwrap();
+            assert_eq!((last.timestamp, last.username.as_str(), last.outcome), (clock.now(), "j.everyman", AuditOutcome::Success));
+        }
+
+        // 不存在的用户名同样计入失败，避免用错误类型区分用户是否存在
+        #[test]
+        fn unknown_usernames_count_as_failures() {
+            let accounts = accounts();
+            let clock = MockClock::new(1_675_584_000);
+            let mut throttle = LoginThrottle::new(&clock, ThrottlePolicy::default());
+            let mut audit = MemorySink::default();
+            assert_eq!(attempt(&accounts, &mut throttle, &mut audit, "nobody", "x"), Err(LoginError::InvalidCredentials));
+            assert_eq!(throttle.failures("nobody"), 1);
+        }
+
+        // 用随机用户名不停地失败，失败记录也不会无限增长：过期的记录会被清理掉
+        #[test]
+        fn stale_failure_records_are_forgotten() {
+            let clock = MockClock::new(1_675_584_000);
+            let policy = ThrottlePolicy { forget_after: 60, ..ThrottlePolicy::default() };
+            let mut throttle = LoginThrottle::new(&clock, policy);
+            for i in 0..5000 {
+                clock.advance(1);
+                throttle.record_failure(&format!("bot{}", i));
+            }
+            assert!(throttle.tracked_users() <= 1024, "{} entries", throttle.tracked_users());
+            assert_eq!((throttle.failures("bot4999"), throttle.failures("bot0")), (1, 0));
+        }
+
+        // 一分钟内所有用户名累计失败达到上限后，任何人都要等最早的那次失败滑出窗口
+        #[test]
+        fn global_limit_throttles_every_username() {
+            let accounts = accounts();
+            let clock = MockClock::new(1_675_584_000);
+            let policy = ThrottlePolicy { global_limit: 3, ..ThrottlePolicy::default() };
+            let mut throttle = LoginThrottle::new(&clock, policy);
+            let mut audit = MemorySink::default();
+            for name in ["a1", "b2", "c3"] {
+                clock.advance(10);
+                assert_eq!(attempt(&accounts, &mut throttle, &mut audit, name, "x"), Err(LoginError::InvalidCredentials));
+            }
+            assert_eq!(attempt(&accounts, &mut throttle, &mut audit, "j.everyman", "password123"),
+                       Err(LoginError::Throttled { retry_after: 40 }));
+            clock.advance(40);
+            assert_eq!(attempt(&accounts, &mut throttle, &mut audit, "j.everyman", "password123"), Ok(()));
+        }
+
+        // 启用了两步验证，口令对了还要验证码；验证码错误同样计入失败次数，受到退避的限制
+        #[test]
+        fn second_factor_is_required_and_failures_are_throttled() {
+            use crate::totp::{base32_decode, totp, TotpConfig, TotpStore};
+            let accounts = accounts();
+            let clock = MockClock::new(1_675_584_000);
+            let config = TotpConfig::default();
+            let mut two_factor = TotpStore::new(&clock, "RustByExample", config);
+            let provisioning = two_factor.enroll("j.everyman").unwrap();
+            let key = base32_decode(&provisioning.secret_base32).unwrap();
+            let code_at = |time: u64| totp(&key, time, &config);
+            let mut throttle = LoginThrottle::new(&clock, ThrottlePolicy::default());
+            let mut audit = MemorySink::default();
+            let mut logon = |throttle: &mut LoginThrottle<_>, two_factor: &mut TotpStore<_>, code: Option<&str>| {
+                try_logon_with_totp(&accounts, throttle, &mut audit, two_factor, "j.everyman", &Secret::from("password123"), code)
+                    .map(|_| ())
+            };
+            // 还没确认的绑定不算启用，只要口令
+            assert_eq!(logon(&mut throttle, &mut two_factor, None), Ok(()));
+            two_factor.confirm("j.everyman", &code_at(clock.now())).unwrap();
+            assert_eq!(logon(&mut throttle, &mut two_factor, None), Err(LoginError::SecondFactorRequired));
+            // 确认时用掉了当前时间步，过一分钟再登录，用上一个时间步的验证码
+            clock.advance(60);
+            let previous = code_at(clock.now() - 30);
+            assert_eq!(logon(&mut throttle, &mut two_factor, Some(&previous)), Ok(()));
+            assert_eq!(logon(&mut throttle, &mut two_factor, Some(&previous)), Err(LoginError::InvalidSecondFactor));
+            let too_old = code_at(clock.now() - 90);
+            assert_eq!(logon(&mut throttle, &mut two_factor, Some(&too_old)), Err(LoginError::Throttled { retry_after: 1 }));
+            clock.advance(1);
+            assert_eq!(logon(&mut throttle, &mut two_factor, Some(&too_old)), Err(LoginError::InvalidSecondFactor));
+            clock.advance(60);
+            assert_eq!(logon(&mut throttle, &mut two_factor, Some(&code_at(clock.now()))), Ok(()));
+            assert_eq!(throttle.failures("j.everyman"), 0);
+        }
+    }
+}
+
+mod session {
+    use std::collections::HashMap;
+    use std::fmt;
+    use crate::clock::Clock;
+    use crate::credential::{hex, random_bytes, Sha256};
+
+    const TOKEN_BYTES: usize = 32;
+
+    // 令牌本身就是凭据，Debug 只显示前几位
+    #[derive(Clone, PartialEq, Eq)]
+    pub struct SessionToken(String);
+
+    impl SessionToken {
+        fn generate() -> SessionToken {
+            let mut bytes = [0u8; TOKEN_BYTES];
+            random_bytes(&mut bytes);
+            SessionToken(hex(&bytes))
+        }
+
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
+
+        // 存储里只保存令牌的散列，存储内容泄露也拿不到可用的令牌
+        fn digest(token: &str) -> [u8; 32] {
+            Sha256::digest(token.as_bytes())
+        }
+    }
+
+    impl fmt::Debug for SessionToken {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "SessionToken({}…)", &self.0[..8])
+        }
+    }
+
+    // 登录成功时发给用户的会话
+    #[derive(Debug, Clone)]
+    pub struct Session {
+        pub token: SessionToken,
+        pub username: String,
+        pub expires_at: u64,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct SessionPolicy {
+        // 从签发（或刷新）开始最多有效多少秒
+        pub ttl: u64,
+        // 多少秒没有使用就失效
+        pub idle_timeout: u64,
+        // 从第一次签发（登录）算起的绝对上限，刷新也不能超过，之后必须重新登录
+        pub max_lifetime: u64,
+    }
+
+    impl Default for SessionPolicy {
+        fn default() -> Self {
+            SessionPolicy { ttl: 8 * 3600, idle_timeout: 30 * 60, max_lifetime: 7 * 24 * 3600 }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum SessionError {
+        // 不存在或已被吊销
+        Invalid,
+        Expired,
+        IdleTimeout,
+    }
+
+    impl fmt::Display for SessionError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                SessionError::Invalid => write!(f, "invalid session"),
+                SessionError::Expired => write!(f, "session expired"),
+                SessionError::IdleTimeout => write!(f, "session timed out due to inactivity"),
+            }
+        }
+    }
+
+    struct SessionState {
+        username: String,
+        // 登录的时间，刷新时沿用
+        issued_at: u64,
+        expires_at: u64,
+        last_seen: u64,
+    }
+
+    pub struct SessionStore<C: Clock> {
+        clock: C,
+        policy: SessionPolicy,
+        sessions: HashMap<[u8; 32], SessionState>,
+    }
+
+    impl<C: Clock> SessionStore<C> {
+        pub fn new(clock: C, policy: SessionPolicy) -> Self {
+            SessionStore { clock, policy, sessions: HashMap::new() }
+        }
+
+        pub fn len(&self) -> usize {
+            self.sessions.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.sessions.is_empty()
+        }
+
+        pub fn issue(&mut self, username: &str) -> Session {
+            let now = self.clock.now();
+            self.issue_since(username, now)
+        }
+
+        fn issue_since(&mut self, username: &str, issued_at: u64) -> Session {
+            let now = self.clock.now();
+            let token = SessionToken::generate();
+            let expires_at = (now + self.policy.ttl).min(issued_at + self.policy.max_lifetime);
+            self.sessions.insert(SessionToken::digest(token.as_str()), SessionState {
+                username: username.to_string(),
+                issued_at,
+                expires_at,
+                last_seen: now,
+            });
+            Session { token, username: username.to_string(), expires_at }
+        }
+
+        // 令牌有效时返回用户名，并把它记为刚刚使用过；失效的令牌顺便删掉
+        pub fn validate(&mut self, token: &str) -> Result<&str, SessionError> {
+            let now = self.clock.now();
+            let key = SessionToken::digest(token);
+            let state = self.sessions.get(&key).ok_or(SessionError::Invalid)?;
+            let error = if now >= state.expires_at {
+                Some(SessionError::Expired)
+            } else if now >= state.last_seen + self.policy.idle_timeout {
+                Some(SessionError::IdleTimeout)
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                self.sessions.remove(&key);
+                return Err(error);
+            }
+            let state = self.sessions.get_mut(&key).ok_or(SessionError::Invalid)?;
+            state.last_seen = now;
+            Ok(&state.username)
+        }
+
+        // 换一个新令牌并重新计算过期时间，旧令牌立即失效。
+        // 过期时间不会超过登录时间 + max_lifetime，一直刷新也不能让会话永远有效
+        pub fn refresh(&mut self, token: &str) -> Result<Session, SessionError> {
+            let username = self.validate(token)?.to_string();
+            let state = self.sessions.remove(&SessionToken::digest(token)).ok_or(SessionError::Invalid)?;
+            Ok(self.issue_since(&username, state.issued_at))
+        }
+
+        pub fn revoke(&mut self, token: &str) -> bool {
+            self.sessions.remove(&SessionToken::digest(token)).is_some()
+        }
+
+        // 吊销某个用户的所有会话（比如改了口令之后），返回吊销的个数
+        pub fn revoke_all(&mut self, username: &str) -> usize {
+            let before = self.sessions.len();
+            self.sessions.retain(|_, state| !state.username.eq_ignore_ascii_case(username));
+            before - self.sessions.len()
+        }
+
+        // 清理已经过期或闲置超时的会话
+        pub fn purge_expired(&mut self) -> usize {
+            let now = self.clock.now();
+            let idle_timeout = self.policy.idle_timeout;
+            let before = self.sessions.len();
+            self.sessions.retain(|_, state| now < state.expires_at && now < state.last_seen + idle_timeout);
+            before - self.sessions.len()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::MockClock;
+
+        const START: u64 = 1_675_584_000;
+
+        // 签发后最多 8 小时，闲置 30 分钟失效；刷新会换新令牌，旧令牌立即作废
+        #[test]
+        fn refresh_replaces_the_token_and_idle_sessions_expire() {
+            let clock = MockClock::new(START);
+            let mut sessions = SessionStore::new(&clock, SessionPolicy::default());
+            let session = sessions.issue("j.everyman");
+            assert_eq!((session.username.as_str(), session.expires_at), ("j.everyman", START + 8 * 3600));
+            clock.advance(20 * 60);
+            assert_eq!(sessions.validate(session.token.as_str()), Ok("j.everyman"));
+            clock.advance(20 * 60);
+            // 上次使用是 20 分钟前，还没闲置超时
+            let refreshed = sessions.refresh(session.token.as_str()).unwrap();
+            assert_ne!(refreshed.token, session.token);
+            assert_eq!(sessions.validate(session.token.as_str()), Err(SessionError::Invalid));
+            assert_eq!(refreshed.expires_at, START + 40 * 60 + 8 * 3600);
+            clock.advance(31 * 60);
+            assert_eq!(sessions.validate(refreshed.token.as_str()), Err(SessionError::IdleTimeout));
+            assert!(sessions.is_empty());
+        }
+
+        // 一直在用也逃不过有效期
+        #[test]
+        fn active_sessions_still_expire() {
+            let clock = MockClock::new(START);
+            let mut sessions = SessionStore::new(&clock, SessionPolicy::default());
+            let session = sessions.issue("j.everyman");
+            for _ in 0..16 {
+                clock.advance(29 * 60);
+                sessions.validate(session.token.as_str()).unwrap();
+            }
+            clock.advance(29 * 60);
+            assert_eq!(sessions.validate(session.token.as_str()), Err(SessionError::Expired));
+        }
+
+        // 刷新会换新的过期时间，但不会超过登录时间 + max_lifetime
+        #[test]
+        fn refreshing_cannot_outlive_max_lifetime() {
+            let clock = MockClock::new(START);
+            let policy = SessionPolicy { max_lifetime: 24 * 3600, ..SessionPolicy::default() };
+            let mut sessions = SessionStore::new(&clock, policy);
+            let mut session = sessions.issue("j.everyman");
+            let mut refreshes = 0;
+            loop {
+                clock.advance(29 * 60);
+                match sessions.refresh(session.token.as_str()) {
+                    Ok(next) => session = next,
+                    Err(why) => {
+                        assert_eq!(why, SessionError::Expired);
+                        break;
+                    }
+                }
+                assert!(session.expires_at <= START + 24 * 3600);
+                refreshes += 1;
+            }
+            assert_eq!(refreshes, 24 * 60 / 29);
+        }
+
+        #[test]
+        fn revoke_and_purge() {
+            let clock = MockClock::new(START);
+            let mut sessions = SessionStore::new(&clock, SessionPolicy::default());
+            let first = sessions.issue("j.everyman");
+            let second = sessions.issue("J.Everyman");
+            let other = sessions.issue("jane");
+            assert!(sessions.revoke(first.token.as_str()));
+            assert!(!sessions.revoke(first.token.as_str()));
+            assert_eq!(sessions.revoke_all("j.everyman"), 1);
+            assert_eq!(sessions.validate(second.token.as_str()), Err(SessionError::Invalid));
+            clock.advance(31 * 60);
+            assert_eq!(sessions.purge_expired(), 1);
+            assert_eq!(sessions.validate(other.token.as_str()), Err(SessionError::Invalid));
+            assert_eq!(sessions.len(), 0);
+        }
+    }
+}
+
+mod audit {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::Path;
+    use crate::clock::format_timestamp;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum AuditOutcome {
+        Success,
+        Failure,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AuditEvent {
+        pub timestamp: u64,
+        pub username: String,
+        pub outcome: AuditOutcome,
+        // 失败原因的代码，成功时为 None
+        pub reason: Option<&'static str>,
+    }
+
+    impl AuditEvent {
+        // 一个事件一行 JSON
+        pub fn to_json(&self) -> String {
+            let outcome = match self.outcome {
+                AuditOutcome::Success => "success",
+                AuditOutcome::Failure => "failure",
+            };
+            let reason = match self.reason {
+                Some(reason) => json_string(reason),
+                None => "null".to_string(),
+            };
+            format!("{{\"timestamp\":{},\"time\":{},\"username\":{},\"outcome\":\"{}\",\"reason\":{}}}",
+                    self.timestamp, json_string(&format_timestamp(self.timestamp)),
+                    json_string(&self.username), outcome, reason)
+        }
+    }
+
+    fn json_string(s: &str) -> String {
+        let mut out = String::with_capacity(s.len() + 2);
+        out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    // 审计事件写到哪里由调用方决定
+    pub trait AuditSink {
+        fn record(&mut self, event: &AuditEvent) -> io::Result<()>;
+    }
+
+    // 保存在内存里，方便检查
+    #[derive(Debug, Default)]
+    pub struct MemorySink {
+        pub events: Vec<AuditEvent>,
+    }
+
+    impl AuditSink for MemorySink {
+        fn record(&mut self, event: &AuditEvent) -> io::Result<()> {
+            self.events.push(event.clone());
+            Ok(())
+        }
+    }
+
+    // 追加写到 JSON Lines 文件，每条事件写完立刻 flush，程序崩溃也不会丢掉已经记录的事件
+    pub struct JsonLinesSink {
+        file: File,
+    }
+
+    impl JsonLinesSink {
+        pub fn create<P: AsRef<Path>>(path: P) -> io::Result<JsonLinesSink> {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Ok(JsonLinesSink { file })
+        }
+    }
+
+    impl AuditSink for JsonLinesSink {
+        fn record(&mut self, event: &AuditEvent) -> io::Result<()> {
+            // 先拼好整行再一次写入，多个进程追加同一个文件时行不会交错
+            let mut line = event.to_json();
+            line.push('\n');
+            self.file.write_all(line.as_bytes())?;
+            self.file.flush()
+        }
+    }
+}
+
+mod accounts_db {
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use crate::accounts::{AccountInfo, AccountRecord, AccountStore};
+    use crate::atomic_file;
+    use crate::credential::{hex, KdfParams, PasswordHash, Sha256};
+    use crate::phone_number::PhoneNumber;
+
+    // 文件格式：
+    //      accounts-db <版本号>
+    //      sha256 <正文的 SHA-256>
+    //      <正文：每行一个账号，字段用 tab 分隔>
+    // 版本 1：username, credential, name, email
+    // 版本 2：在版本 1 后面加上 phone（可以为空）
+    // AccountInfo 再加字段时：CURRENT_VERSION 加一，在 migrate 里补一步，把旧记录补齐
+    const MAGIC: &str = "accounts-db";
+    pub const CURRENT_VERSION: u32 = 2;
+
+    #[derive(Debug)]
+    pub enum DbError {
+        Io(io::Error),
+        // 文件头不对或校验和不符
+        Corrupted(String),
+        // 比当前程序还新的版本，不知道怎么读
+        UnsupportedVersion(u32),
+        // 第 line 行（正文中）的记录有问题
+        InvalidRecord { line: usize, reason: String },
+    }
+
+    impl fmt::Display for DbError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                DbError::Io(e) => write!(f, "{}", e),
+                DbError::Corrupted(why) => write!(f, "account database is corrupted: {}", why),
+                DbError::UnsupportedVersion(v) => write!(f, "unsupported account database version {}", v),
+                DbError::InvalidRecord { line, reason } => write!(f, "record {}: {}", line, reason),
+            }
+        }
+    }
+
+    impl From<io::Error> for DbError {
+        fn from(e: io::Error) -> Self {
+            DbError::Io(e)
+        }
+    }
+
+    // 字段里的 \ tab 换行 需要转义
+    fn escape(field: &str) -> String {
+        field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+    }
+
+    fn unescape(field: &str) -> String {
+        let mut out = String::with_capacity(field.len());
+        let mut chars = field.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            }
+        }
+        out
+    }
+
+    // 加上文件头和校验和
+    pub fn encode(version: u32, body: &str) -> String {
+        format!("{} {}\nsha256 {}\n{}", MAGIC, version, hex(&Sha256::digest(body.as_bytes())), body)
+    }
+
+    fn decode(contents: &str) -> Result<(u32, &str), DbError> {
+        let corrupted = |why: &str| DbError::Corrupted(why.to_string());
+        let (header, rest) = contents.split_once('\n').ok_or_else(|| corrupted("missing header"))?;
+        let (checksum, body) = rest.split_once('\n').ok_or_else(|| corrupted("missing checksum"))?;
+        let version = header.strip_prefix(MAGIC)
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .ok_or_else(|| corrupted("bad header"))?;
+        if version == 0 || version > CURRENT_VERSION {
+            return Err(DbError::UnsupportedVersion(version));
+        }
+        let expected = checksum.strip_prefix("sha256 ").ok_or_else(|| corrupted("bad checksum line"))?;
+        if hex(&Sha256::digest(body.as_bytes())) != expected {
+            return Err(corrupted("checksum mismatch"));
+        }
+        Ok((version, body))
+    }
+
+    // 把 from 版本的一条记录升级到当前版本
+    fn migrate(from: u32, mut fields: Vec<String>) -> Vec<String> {
+        let mut version = from;
+        while version < CURRENT_VERSION {
+            match version {
+                // 1 -> 2：加了 phone
+                1 => fields.push(String::new()),
+                _ => unreachable!("no migration from version {}", version),
+            }
+            version += 1;
+        }
+        fields
+    }
+
+    fn parse_record(fields: &[String]) -> Result<(String, AccountRecord), String> {
+        match fields {
+            [username, credential, name, email, phone] => {
+                let credential = credential.parse::<PasswordHash>().map_err(|e| e.to_string())?;
+                let mut info = AccountInfo::new(name, email);
+                if !phone.is_empty() {
+                    info = info.with_phone(PhoneNumber::parse(phone).map_err(|e| e.to_string())?);
+                }
+                Ok((username.clone(), AccountRecord { credential, info }))
+            }
+            _ => Err(format!("expected 5 fields, found {}", fields.len())),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, params: KdfParams) -> Result<AccountStore, DbError> {
+        let contents = fs::read_to_string(path)?;
+        let (version, body) = decode(&contents)?;
+        let mut store = AccountStore::with_params(params);
+        for (index, line) in body.lines().enumerate() {
+            let invalid = |reason: String| DbError::InvalidRecord { line: index + 1, reason };
+            let fields = migrate(version, line.split('\t').map(unescape).collect());
+            let (username, record) = parse_record(&fields).map_err(invalid)?;
+            store.insert_record(&username, record).map_err(|e| invalid(e.to_string()))?;
+        }
+        Ok(store)
+    }
+
+    // 总是按当前版本写
+    pub fn save<P: AsRef<Path>>(store: &AccountStore, path: P) -> Result<(), DbError> {
+        let mut body = String::new();
+        for (username, record) in store.records() {
+            let phone = record.info.phone.as_ref().map(|p| p.to_string()).unwrap_or_default();
+            let fields = [username.to_string(), record.credential.to_string(),
+                          record.info.name.clone(), record.info.email.clone(), phone];
+            let fields: Vec<String> = fields.iter().map(|f| escape(f)).collect();
+            body.push_str(&fields.join("\t"));
+            body.push('\n');
+        }
+        atomic_file::write(path.as_ref(), encode(CURRENT_VERSION, &body).as_bytes())?;
+        Ok(())
+    }
+
+}
+
+mod permissions {
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::fmt;
+    use crate::accounts::AccountStore;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub enum Action {
+        Read,
+        Write,
+        Delete,
+        // 管理权限（比如管理其他人的账号）
+        Admin,
+    }
+
+    // 一条权限：允许对匹配 resource 的资源做 action。
+    // resource 可以是具体的资源（"contacts/daniel"），也可以是前缀通配（"contacts/*"），"*" 匹配一切
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct Permission {
+        pub action: Action,
+        pub resource: String,
+    }
+
+    impl Permission {
+        pub fn new(action: Action, resource: &str) -> Permission {
+            Permission { action, resource: resource.to_string() }
+        }
+
+        fn matches(&self, action: Action, resource: &str) -> bool {
+            if self.action != action {
+                return false;
+            }
+            match self.resource.strip_suffix('*') {
+                Some(prefix) => resource.starts_with(prefix),
+                None => self.resource == resource,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum PolicyError {
+        UnknownRole(String),
+        // 没有这个账号
+        UnknownUser(String),
+        RoleExists(String),
+        // 加上这个继承关系会形成环
+        InheritanceCycle(String),
+    }
+
+    impl fmt::Display for PolicyError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                PolicyError::UnknownRole(role) => write!(f, "unknown role {:?}", role),
+                PolicyError::UnknownUser(username) => write!(f, "no account named {:?}", username),
+                PolicyError::RoleExists(role) => write!(f, "role {:?} already exists", role),
+                PolicyError::InheritanceCycle(role) => write!(f, "role {:?} would inherit from itself", role),
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Role {
+        permissions: HashSet<Permission>,
+        // 继承这些角色的全部权限
+        parents: BTreeSet<String>,
+    }
+
+    // 角色、角色的权限和继承关系，以及每个用户名被分配的角色
+    #[derive(Debug, Default)]
+    pub struct Policy {
+        roles: HashMap<String, Role>,
+        assignments: HashMap<String, BTreeSet<String>>,
+    }
+
+    impl Policy {
+        pub fn new() -> Policy {
+            Policy::default()
+        }
+
+        fn role_mut(&mut self, role: &str) -> Result<&mut Role, PolicyError> {
+            self.roles.get_mut(role).ok_or_else(|| PolicyError::UnknownRole(role.to_string()))
+        }
+
+        // 定义一个角色，parents 里的角色必须已经存在
+        pub fn define_role(&mut self, role: &str, parents: &[&str]) -> Result<(), PolicyError> {
+            if self.roles.contains_key(role) {
+                return Err(PolicyError::RoleExists(role.to_string()));
+            }
+            if let Some(unknown) = parents.iter().find(|parent| !self.roles.contains_key(**parent)) {
+                return Err(PolicyError::UnknownRole(unknown.to_string()));
+            }
+            let parents = parents.iter().map(|parent| parent.to_string()).collect();
+            self.roles.insert(role.to_string(), Role { permissions: HashSet::new(), parents });
+            Ok(())
+        }
+
+        // 给已有的角色再加一个父角色
+        pub fn inherit(&mut self, role: &str, parent: &str) -> Result<(), PolicyError> {
+            if !self.roles.contains_key(parent) {
+                return Err(PolicyError::UnknownRole(parent.to_string()));
+            }
+            if self.ancestors(parent).contains(role) {
+                return Err(PolicyError::InheritanceCycle(role.to_string()));
+            }
+            self.role_mut(role)?.parents.insert(parent.to_string());
+            Ok(())
+        }
+
+        // role 自己以及它直接、间接继承的所有角色
+        fn ancestors(&self, role: &str) -> HashSet<String> {
+            let mut seen = HashSet::new();
+            let mut stack = vec![role.to_string()];
+            while let Some(role) = stack.pop() {
+                if let Some(definition) = self.roles.get(&role) {
+                    stack.extend(definition.parents.iter().filter(|parent| !seen.contains(*parent)).cloned());
+                }
+                seen.insert(role);
+            }
+            seen
+        }
+
+        // 返回 false 表示这条权限本来就有
+        pub fn grant(&mut self, role: &str, permission: Permission) -> Result<bool, PolicyError> {
+            Ok(self.role_mut(role)?.permissions.insert(permission))
+        }
+
+        // 返回 false 表示本来就没有这条权限
+        pub fn revoke(&mut self, role: &str, permission: &Permission) -> Result<bool, PolicyError> {
+            Ok(self.role_mut(role)?.permissions.remove(permission))
+        }
+
+        // 只能分配给 accounts 里已有的账号；用户名不区分大小写，和 AccountStore 一致
+        pub fn assign(&mut self, accounts: &AccountStore, username: &str, role: &str) -> Result<bool, PolicyError> {
+            if !self.roles.contains_key(role) {
+                return Err(PolicyError::UnknownRole(role.to_string()));
+            }
+            if accounts.get(username).is_none() {
+                return Err(PolicyError::UnknownUser(username.to_string()));
+            }
+            Ok(self.assignments.entry(username.to_ascii_lowercase()).or_default().insert(role.to_string()))
+        }
+
+        pub fn unassign(&mut self, username: &str, role: &str) -> bool {
+            let username = username.to_ascii_lowercase();
+            let removed = self.assignments.get_mut(&username).is_some_and(|roles| roles.remove(role));
+            if self.assignments.get(&username).is_some_and(BTreeSet::is_empty) {
+                self.assignments.remove(&username);
+            }
+            removed
+        }
+
+        // 删除账号时由 account_lifecycle::delete 调用，去掉这个用户的所有角色
+        pub fn forget_user(&mut self, username: &str) {
+            self.assignments.remove(&username.to_ascii_lowercase());
+        }
+
+        // 用户直接分配的角色加上继承来的角色
+        pub fn effective_roles(&self, username: &str) -> BTreeSet<String> {
+            self.assignments.get(&username.to_ascii_lowercase())
+                .into_iter()
+                .flatten()
+                .flat_map(|role| self.ancestors(role))
+                .collect()
+        }
+
+        pub fn can(&self, username: &str, action: Action, resource: &str) -> bool {
+            self.effective_roles(username).iter()
+                .filter_map(|role| self.roles.get(role))
+                .any(|role| role.permissions.iter().any(|p| p.matches(action, resource)))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::accounts::AccountInfo;
+        use crate::credential::{KdfParams, Secret};
+
+        fn accounts() -> AccountStore {
+            let mut accounts = AccountStore::with_params(KdfParams::FAST);
+            accounts.register("j.everyman", Secret::from("password123"), AccountInfo::new("John Everyman", "j.everyman@gmail.com"))
+                .unwrap();
+            accounts
+        }
+
+        // viewer <- editor <- admin
+        fn policy() -> Policy {
+            let mut policy = Policy::new();
+            policy.define_role("viewer", &[]).unwrap();
+            policy.define_role("editor", &["viewer"]).unwrap();
+            policy.define_role("admin", &["editor"]).unwrap();
+            policy.grant("viewer", Permission::new(Action::Read, "contacts/*")).unwrap();
+            policy.grant("editor", Permission::new(Action::Write, "contacts/*")).unwrap();
+            policy.grant("admin", Permission::new(Action::Delete, "*")).unwrap();
+            policy.grant("admin", Permission::new(Action::Admin, "accounts/*")).unwrap();
+            policy
+        }
+
+        #[test]
+        fn roles_inherit_the_permissions_of_their_parents() {
+            let accounts = accounts();
+            let mut policy = policy();
+            assert_eq!(policy.grant("viewer", Permission::new(Action::Read, "contacts/*")), Ok(false));
+            policy.assign(&accounts, "J.Everyman", "editor").unwrap();
+            assert_eq!(policy.effective_roles("j.everyman").into_iter().collect::<Vec<_>>(), ["editor", "viewer"]);
+            assert!(policy.can("j.everyman", Action::Read, "contacts/daniel"));
+            assert!(policy.can("j.everyman", Action::Write, "contacts/daniel"));
+            assert!(!policy.can("j.everyman", Action::Delete, "contacts/daniel"));
+            assert!(!policy.can("j.everyman", Action::Read, "accounts/jane"));
+            assert!(!policy.can("nobody", Action::Read, "contacts/daniel"));
+        }
+
+        #[test]
+        fn assigning_and_unassigning_a_role() {
+            let accounts = accounts();
+            let mut policy = policy();
+            policy.assign(&accounts, "j.everyman", "editor").unwrap();
+            assert_eq!(policy.assign(&accounts, "j.everyman", "admin"), Ok(true));
+            assert!(policy.can("j.everyman", Action::Delete, "contacts/daniel"));
+            assert!(policy.can("j.everyman", Action::Admin, "accounts/jane"));
+            assert!(policy.unassign("j.everyman", "admin"));
+            assert!(!policy.unassign("j.everyman", "admin"));
+            assert!(!policy.can("j.everyman", Action::Admin, "accounts/jane"));
+            assert!(policy.can("j.everyman", Action::Write, "contacts/daniel"));
+            policy.forget_user("j.everyman");
+            assert!(policy.effective_roles("j.everyman").is_empty());
+        }
+
+        // 从父角色撤销权限，所有继承它的角色都会失去这条权限；具体资源的权限只对这个资源有效
+        #[test]
+        fn revoking_from_a_parent_role_affects_children() {
+            let accounts = accounts();
+            let mut policy = policy();
+            policy.assign(&accounts, "j.everyman", "admin").unwrap();
+            assert_eq!(policy.revoke("viewer", &Permission::new(Action::Read, "contacts/*")), Ok(true));
+            assert_eq!(policy.revoke("viewer", &Permission::new(Action::Read, "contacts/*")), Ok(false));
+            assert!(!policy.can("j.everyman", Action::Read, "contacts/daniel"));
+            policy.grant("viewer", Permission::new(Action::Read, "contacts/daniel")).unwrap();
+            assert!(policy.can("j.everyman", Action::Read, "contacts/daniel"));
+            assert!(!policy.can("j.everyman", Action::Read, "contacts/daniella"));
+        }
+
+        #[test]
+        fn invalid_definitions_and_assignments_are_rejected() {
+            let accounts = accounts();
+            let mut policy = policy();
+            assert_eq!(policy.define_role("viewer", &[]), Err(PolicyError::RoleExists("viewer".to_string())));
+            assert_eq!(policy.define_role("auditor", &["nobody"]), Err(PolicyError::UnknownRole("nobody".to_string())));
+            assert_eq!(policy.inherit("viewer", "admin"), Err(PolicyError::InheritanceCycle("viewer".to_string())));
+            assert_eq!(policy.assign(&accounts, "j.everyman", "root"), Err(PolicyError::UnknownRole("root".to_string())));
+            // 只能给已有的账号分配角色
+            assert_eq!(policy.assign(&accounts, "mallory", "admin"), Err(PolicyError::UnknownUser("mallory".to_string())));
+        }
+    }
+}
+
+mod totp {
+    use std::collections::HashMap;
+    use std::fmt;
+    use crate::clock::Clock;
+    use crate::credential::{constant_time_eq, hmac_sha1, hmac_sha512, random_bytes, HmacSha256, Secret, Sha256};
+
+    const SECRET_LEN: usize = 20;
+    // RFC 4226 要求至少 6 位；超过 9 位 10^digits 就超出 u32 了
+    const MIN_DIGITS: u32 = 6;
+    const MAX_DIGITS: u32 = 9;
+    // 容许的时钟误差最多前后各 10 个时间步，再大就是在猜验证码了
+    const MAX_SKEW: u64 = 10;
+    const RECOVERY_CODES: usize = 10;
+
+    // RFC 6238 允许的三种 HMAC 算法，大多数验证器 App 只支持 SHA1
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Algorithm {
+        Sha1,
+        Sha256,
+        Sha512,
+    }
+
+    impl Algorithm {
+        fn name(self) -> &'static str {
+            match self {
+                Algorithm::Sha1 => "SHA1",
+                Algorithm::Sha256 => "SHA256",
+                Algorithm::Sha512 => "SHA512",
+            }
+        }
+    }
+
+    fn hmac(algorithm: Algorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match algorithm {
+            Algorithm::Sha1 => hmac_sha1(key, message).to_vec(),
+            Algorithm::Sha256 => {
+                let mut mac = HmacSha256::new(key);
+                mac.update(message);
+                mac.finish().to_vec()
+            }
+            Algorithm::Sha512 => hmac_sha512(key, message).to_vec(),
+        }
+    }
+
+    // 字段不公开，只能通过 new 构造，保证 digits 和 period 都在合法范围内
+    #[derive(Debug, Clone, Copy)]
+    pub struct TotpConfig {
+        algorithm: Algorithm,
+        // 验证码的位数
+        digits: u32,
+        // 每个验证码的有效时长（秒）
+        period: u64,
+        // 前后各容许多少个 period 的时钟误差
+        skew: u64,
+    }
+
+    impl Default for TotpConfig {
+        fn default() -> Self {
+            TotpConfig { algorithm: Algorithm::Sha1, digits: 6, period: 30, skew: 1 }
+        }
+    }
+
+    impl TotpConfig {
+        pub fn new(algorithm: Algorithm, digits: u32, period: u64) -> Result<TotpConfig, TotpError> {
+            if !(MIN_DIGITS..=MAX_DIGITS).contains(&digits) {
+                return Err(TotpError::InvalidConfig("digits must be between 6 and 9"));
+            }
+            if period == 0 {
+                return Err(TotpError::InvalidConfig("period must be positive"));
+            }
+            Ok(TotpConfig { algorithm, digits, period, skew: 1 })
+        }
+
+        pub fn with_skew(mut self, skew: u64) -> Result<TotpConfig, TotpError> {
+            if skew > MAX_SKEW {
+                return Err(TotpError::InvalidConfig("skew must be at most 10"));
+            }
+            self.skew = skew;
+            Ok(self)
+        }
+
+        pub fn digits(&self) -> u32 {
+            self.digits
+        }
+
+        pub fn period(&self) -> u64 {
+            self.period
+        }
+    }
+
+    // RFC 4226 的 HOTP：HMAC 之后做动态截断，取 digits 位十进制数
+    fn hotp(key: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> u32 {
+        let mac = hmac(algorithm, key, &counter.to_be_bytes());
+        let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([mac[offset] & 0x7f, mac[offset + 1], mac[offset + 2], mac[offset + 3]]);
+        binary % 10u32.pow(digits)
+    }
+
+    // RFC 6238 的 TOTP：计数器就是 Unix 时间除以 period
+    pub fn totp(key: &[u8], time: u64, config: &TotpConfig) -> String {
+        let code = hotp(key, time / config.period, config.digits, config.algorithm);
+        format!("{:0width$}", code, width = config.digits as usize)
+    }
+
+    const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    // RFC 4648 的 base32，不带 = 填充（otpauth URI 里就是这么用的）
+    pub fn base32_encode(bytes: &[u8]) -> String {
+        let mut out = String::new();
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for &byte in bytes {
+            buffer = (buffer << 8) | byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+            }
+        }
+        if bits > 0 {
+            out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+        }
+        out
+    }
+
+    // 忽略大小写、空格和末尾的 =
+    pub fn base32_decode(s: &str) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for c in s.trim_end_matches('=').chars().filter(|c| !c.is_whitespace()) {
+            let value = BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())? as u32;
+            buffer = (buffer << 5) | value;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((buffer >> bits) as u8);
+            }
+        }
+        Some(out)
+    }
+
+    fn percent_encode(s: &str) -> String {
+        s.bytes().map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        }).collect()
+    }
+
+    // 验证器 App 扫码用的 otpauth:// URI
+    pub fn provisioning_uri(issuer: &str, account: &str, secret: &[u8], config: &TotpConfig) -> String {
+        format!("otpauth://totp/{}:{}?secret={}&issuer={}&algorithm={}&digits={}&period={}",
+                percent_encode(issuer), percent_encode(account), base32_encode(secret),
+                percent_encode(issuer), config.algorithm.name(), config.digits, config.period)
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum TotpError {
+        NotEnrolled,
+        AlreadyEnrolled,
+        InvalidCode,
+        // 同一个时间窗口的验证码只能用一次
+        CodeReused,
+        InvalidConfig(&'static str),
+    }
+
+    impl fmt::Display for TotpError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                TotpError::NotEnrolled => write!(f, "two-factor authentication is not enabled"),
+                TotpError::AlreadyEnrolled => write!(f, "two-factor authentication is already enabled"),
+                TotpError::InvalidCode => write!(f, "invalid verification code"),
+                TotpError::CodeReused => write!(f, "verification code has already been used"),
+                TotpError::InvalidConfig(why) => write!(f, "invalid TOTP configuration: {}", why),
+            }
+        }
+    }
+
+    // 开始绑定时交给用户的信息，只显示这一次
+    #[derive(Debug)]
+    pub struct Provisioning {
+        pub secret_base32: String,
+        pub uri: String,
+        pub recovery_codes: Vec<String>,
+    }
+
+    struct Enrollment {
+        secret: Secret,
+        // 用户输入过一次正确的验证码之后才算启用
+        confirmed: bool,
+        // 最近一次验证通过的时间步，防止验证码被重放
+        last_step: Option<u64>,
+        // 恢复码只保存 SHA-256，用掉一个删一个
+        recovery_codes: Vec<[u8; 32]>,
+    }
+
+    // 恢复码不区分大小写，中间的 - 可有可无
+    fn normalize_recovery_code(code: &str) -> String {
+        code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).collect()
+    }
+
+    fn generate_recovery_code() -> String {
+        let mut bytes = [0u8; 7];
+        random_bytes(&mut bytes);
+        let code = base32_encode(&bytes).to_ascii_lowercase();
+        format!("{}-{}", &code[..5], &code[5..10])
+    }
+
+    pub struct TotpStore<C: Clock> {
+        clock: C,
+        issuer: String,
+        config: TotpConfig,
+        enrollments: HashMap<String, Enrollment>,
+    }
+
+    impl<C: Clock> TotpStore<C> {
+        pub fn new(clock: C, issuer: &str, config: TotpConfig) -> Self {
+            TotpStore { clock, issuer: issuer.to_string(), config, enrollments: HashMap::new() }
+        }
+
+        // 生成新的密钥和恢复码。还没确认的绑定可以重新开始，已经启用的要先 disable
+        pub fn enroll(&mut self, username: &str) -> Result<Provisioning, TotpError> {
+            let key = username.to_ascii_lowercase();
+            if self.is_enabled(&key) {
+                return Err(TotpError::AlreadyEnrolled);
+            }
+            let mut secret = vec![0u8; SECRET_LEN];
+            random_bytes(&mut secret);
+            let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| generate_recovery_code()).collect();
+            let provisioning = Provisioning {
+                secret_base32: base32_encode(&secret),
+                uri: provisioning_uri(&self.issuer, &key, &secret, &self.config),
+                recovery_codes: recovery_codes.clone(),
+            };
+            self.enrollments.insert(key, Enrollment {
+                secret: Secret::from(secret),
+                confirmed: false,
+                last_step: None,
+                recovery_codes: recovery_codes.iter()
+                    .map(|code| Sha256::digest(normalize_recovery_code(code).as_bytes()))
+                    .collect(),
+            });
+            Ok(provisioning)
+        }
+
+        // 用户用 App 生成的第一个验证码确认绑定
+        pub fn confirm(&mut self, username: &str, code: &str) -> Result<(), TotpError> {
+            self.check_code(&username.to_ascii_lowercase(), code, false)?;
+            if let Some(enrollment) = self.enrollments.get_mut(&username.to_ascii_lowercase()) {
+                enrollment.confirmed = true;
+            }
+            Ok(())
+        }
+
+        pub fn is_enabled(&self, username: &str) -> bool {
+            self.enrollments.get(&username.to_ascii_lowercase()).is_some_and(|e| e.confirmed)
+        }
+
+        pub fn disable(&mut self, username: &str) -> bool {
+            self.enrollments.remove(&username.to_ascii_lowercase()).is_some()
+        }
+
+        // 登录时的第二步：验证码或恢复码都可以
+        pub fn verify(&mut self, username: &str, code: &str) -> Result<(), TotpError> {
+            let key = username.to_ascii_lowercase();
+            if !self.is_enabled(&key) {
+                return Err(TotpError::NotEnrolled);
+            }
+            let code = code.trim();
+            if code.len() == self.config.digits as usize && code.chars().all(|c| c.is_ascii_digit()) {
+                self.check_code(&key, code, true)
+            } else {
+                self.use_recovery_code(&key, code)
+            }
+        }
+
+        pub fn recovery_codes_left(&self, username: &str) -> usize {
+            self.enrollments.get(&username.to_ascii_lowercase()).map_or(0, |e| e.recovery_codes.len())
+        }
+
+        // 当前时间前后 skew 个时间步内的验证码都接受，但不接受已经用过的时间步
+        fn check_code(&mut self, key: &str, code: &str, confirmed: bool) -> Result<(), TotpError> {
+            let config = self.config;
+            let now = self.clock.now();
+            let enrollment = self.enrollments.get_mut(key).ok_or(TotpError::NotEnrolled)?;
+            if enrollment.confirmed != confirmed {
+                return Err(if enrollment.confirmed { TotpError::AlreadyEnrolled } else { TotpError::NotEnrolled });
+            }
+            let current = now / config.period;
+            let first = current.saturating_sub(config.skew);
+            let matched = (first..=current + config.skew).find(|&step| {
+                let expected = totp(enrollment.secret.expose(), step * config.period, &config);
+                constant_time_eq(expected.as_bytes(), code.trim().as_bytes())
+            });
+            match matched {
+                None => Err(TotpError::InvalidCode),
+                Some(step) if enrollment.last_step.is_some_and(|last| step <= last) => Err(TotpError::CodeReused),
+                Some(step) => {
+                    enrollment.last_step = Some(step);
+                    Ok(())
+                }
+            }
+        }
+
+        fn use_recovery_code(&mut self, key: &str, code: &str) -> Result<(), TotpError> {
+            let enrollment = self.enrollments.get_mut(key).ok_or(TotpError::NotEnrolled)?;
+            let digest = Sha256::digest(normalize_recovery_code(code).as_bytes());
+            let position = enrollment.recovery_codes.iter().position(|stored| constant_time_eq(stored, &digest));
+            match position {
+                Some(index) => {
+                    enrollment.recovery_codes.swap_remove(index);
+                    Ok(())
+                }
+                None => Err(TotpError::InvalidCode),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::MockClock;
+
+        // RFC 6238 附录 B 的测试向量（8 位验证码，30 秒一个时间步）
+        #[test]
+        fn matches_the_rfc_6238_test_vectors() {
+            let seeds = [(Algorithm::Sha1, &b"12345678901234567890"[..]),
+                         (Algorithm::Sha256, &b"12345678901234567890123456789012"[..]),
+                         (Algorithm::Sha512, &b"1234567890123456789012345678901234567890123456789012345678901234"[..])];
+            let vectors: [(u64, [&str; 3]); 6] = [
+                (59, ["94287082", "46119246", "90693936"]),
+                (1111111109, ["07081804", "68084774", "25091201"]),
+                (1111111111, ["14050471", "67062674", "99943326"]),
+                (1234567890, ["89005924", "91819424", "93441116"]),
+                (2000000000, ["69279037", "90698825", "38618901"]),
+                (20000000000, ["65353130", "77737706", "47863826"]),
+            ];
+            for (time, expected) in vectors {
+                for ((algorithm, seed), expected) in seeds.iter().zip(expected) {
+                    let config = TotpConfig::new(*algorithm, 8, 30).unwrap();
+                    assert_eq!(totp(seed, time, &config), expected, "{:?} at {}", algorithm, time);
+                }
+            }
+        }
+
+        // 位数、时间步长和误差在构造时检查，之后算验证码不会溢出或除以零
+        #[test]
+        fn invalid_configs_are_rejected() {
+            assert_eq!(TotpConfig::new(Algorithm::Sha1, 10, 30).err(), Some(TotpError::InvalidConfig("digits must be between 6 and 9")));
+            assert_eq!(TotpConfig::new(Algorithm::Sha1, 5, 30).err(), Some(TotpError::InvalidConfig("digits must be between 6 and 9")));
+            assert_eq!(TotpConfig::new(Algorithm::Sha1, 6, 0).err(), Some(TotpError::InvalidConfig("period must be positive")));
+            assert_eq!(TotpConfig::new(Algorithm::Sha1, 6, 30).and_then(|config| config.with_skew(2))
+                           .map(|config| (config.digits(), config.period())),
+                       Ok((6, 30)));
+            assert!(TotpConfig::default().with_skew(10).is_ok());
+            assert_eq!(TotpConfig::default().with_skew(11).err(), Some(TotpError::InvalidConfig("skew must be at most 10")));
+            assert_eq!(TotpConfig::default().with_skew(u64::MAX).err(), Some(TotpError::InvalidConfig("skew must be at most 10")));
+        }
+
+        #[test]
+        fn base32_round_trips_and_ignores_padding_and_case() {
+            assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+            assert_eq!(base32_decode("mzxw 6ytb oi======"), Some(b"foobar".to_vec()));
+            assert_eq!(base32_decode("MZXW1"), None);
+        }
+
+        // 绑定后要用第一个验证码确认才启用；没绑定过的用户名和已确认的用户名报不同的错
+        #[test]
+        fn enrollment_is_enabled_only_after_confirmation() {
+            let clock = MockClock::new(1_675_584_000);
+            let config = TotpConfig::default();
+            let mut two_factor = TotpStore::new(&clock, "RustByExample", config);
+            let provisioning = two_factor.enroll("j.everyman").unwrap();
+            assert!(provisioning.uri.starts_with("otpauth://totp/RustByExample:j.everyman?"), "{}", provisioning.uri);
+            assert_eq!(provisioning.recovery_codes.len(), RECOVERY_CODES);
+            let key = base32_decode(&provisioning.secret_base32).unwrap();
+            assert!(!two_factor.is_enabled("j.everyman"));
+            assert_eq!(two_factor.confirm("j.everyman", "not a code"), Err(TotpError::InvalidCode));
+            assert_eq!(two_factor.confirm("stranger", "123456"), Err(TotpError::NotEnrolled));
+            two_factor.confirm("J.Everyman", &totp(&key, clock.now(), &config)).unwrap();
+            assert_eq!(two_factor.confirm("j.everyman", &totp(&key, clock.now(), &config)), Err(TotpError::AlreadyEnrolled));
+            assert!(two_factor.is_enabled("j.everyman"));
+            assert_eq!(two_factor.enroll("j.everyman").err(), Some(TotpError::AlreadyEnrolled));
+        }
+
+        // 前后一个时间步的误差可以接受，同一个时间步的验证码不能用两次
+        #[test]
+        fn codes_within_the_skew_are_accepted_once() {
+            let clock = MockClock::new(1_675_584_000);
+            let config = TotpConfig::default();
+            let mut two_factor = TotpStore::new(&clock, "RustByExample", config);
+            let provisioning = two_factor.enroll("j.everyman").unwrap();
+            let key = base32_decode(&provisioning.secret_base32).unwrap();
+            let code_at = |time: u64| totp(&key, time, &config);
+            two_factor.confirm("j.everyman", &code_at(clock.now())).unwrap();
+            clock.advance(60);
+            let previous = code_at(clock.now() - 30);
+            assert_eq!(two_factor.verify("j.everyman", &previous), Ok(()));
+            assert_eq!(two_factor.verify("j.everyman", &previous), Err(TotpError::CodeReused));
+            assert_eq!(two_factor.verify("j.everyman", &code_at(clock.now() - 90)), Err(TotpError::InvalidCode));
+            assert_eq!(two_factor.verify("j.everyman", &code_at(clock.now())), Ok(()));
+        }
+
+        // 手机丢了可以用恢复码，每个只能用一次；停用之后什么码都不认
+        #[test]
+        fn recovery_codes_are_single_use_and_disable_removes_everything() {
+            let clock = MockClock::new(1_675_584_000);
+            let config = TotpConfig::default();
+            let mut two_factor = TotpStore::new(&clock, "RustByExample", config);
+            let provisioning = two_factor.enroll("j.everyman").unwrap();
+            let key = base32_decode(&provisioning.secret_base32).unwrap();
+            two_factor.confirm("j.everyman", &totp(&key, clock.now(), &config)).unwrap();
+            let recovery = provisioning.recovery_codes[0].to_uppercase();
+            assert_eq!(two_factor.verify("j.everyman", &recovery), Ok(()));
+            assert_eq!(two_factor.verify("j.everyman", &recovery), Err(TotpError::InvalidCode));
+            assert_eq!(two_factor.recovery_codes_left("j.everyman"), RECOVERY_CODES - 1);
+            assert!(two_factor.disable("j.everyman"));
+            assert!(!two_factor.disable("j.everyman"));
+            assert_eq!(two_factor.verify("j.everyman", "123456"), Err(TotpError::NotEnrolled));
+            assert_eq!(two_factor.recovery_codes_left("j.everyman"), 0);
+        }
+    }
+}
+
+// 账号本身存在 AccountStore 里，会话、角色、两步验证各自存在别的地方。
+// 改口令、删账号要经过这里，让别处跟这个账号有关的状态一起失效，
+// 否则账号删掉后有人用同一个用户名重新注册，会继承原来的角色和两步验证
+mod account_lifecycle {
+    use crate::accounts::{AccountError, AccountInfo, AccountStore};
+    use crate::clock::Clock;
+    use crate::credential::Secret;
+    use crate::permissions::Policy;
+    use crate::session::SessionStore;
+    use crate::totp::TotpStore;
+
+    // 改口令，并吊销这个用户已有的全部会话，返回吊销的个数
+    pub fn change_password<C: Clock>(accounts: &mut AccountStore, sessions: &mut SessionStore<C>,
+                                     username: &str, old: &Secret, new: Secret) -> Result<usize, AccountError> {
+        accounts.change_password(username, old, new)?;
+        Ok(sessions.revoke_all(username))
+    }
+
+    // 删除账号，吊销它的会话，去掉它的角色和两步验证，返回账号资料
+    pub fn delete<C: Clock, T: Clock>(accounts: &mut AccountStore, sessions: &mut SessionStore<C>, policy: &mut Policy,
+                                      two_factor: &mut TotpStore<T>, username: &str) -> Result<AccountInfo, AccountError> {
+        let info = accounts.delete(username)?;
+        sessions.revoke_all(username);
+        policy.forget_user(username);
+        two_factor.disable(username);
+        Ok(info)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::MockClock;
+        use crate::credential::KdfParams;
+        use crate::session::{SessionError, SessionPolicy};
+
+        fn accounts() -> AccountStore {
+            let mut accounts = AccountStore::with_params(KdfParams::FAST);
+            accounts.register("jane", Secret::from("correct horse"), AccountInfo::new("Jane Doe", "jane@example.com")).unwrap();
+            accounts
+        }
+
+        // 改了口令，之前登录的会话都要作废
+        #[test]
+        fn changing_the_password_revokes_sessions() {
+            let mut accounts = accounts();
+            let clock = MockClock::new(1_675_584_000);
+            let mut sessions = SessionStore::new(&clock, SessionPolicy::default());
+            let before = sessions.issue("jane");
+            assert_eq!(change_password(&mut accounts, &mut sessions, "Jane", &Secret::from("wrong"), Secret::from("new password")),
+                       Err(AccountError::WrongPassword));
+            assert_eq!(sessions.validate(before.token.as_str()), Ok("jane"));
+            assert_eq!(change_password(&mut accounts, &mut sessions, "Jane", &Secret::from("correct horse"), Secret::from("new password")),
+                       Ok(1));
+            assert_eq!(sessions.validate(before.token.as_str()), Err(SessionError::Invalid));
+            assert!(accounts.verify_password("jane", &Secret::from("new password")).is_ok());
+        }
+
+        // 删除账号时会话、角色和两步验证一起清掉，之后用同一个用户名注册的人什么也继承不到
+        #[test]
+        fn deleting_an_account_clears_roles_two_factor_and_sessions() {
+            use crate::permissions::{Action, Permission, PolicyError};
+            use crate::totp::{base32_decode, totp, TotpConfig};
+            let mut accounts = accounts();
+            let clock = MockClock::new(1_675_584_000);
+            let mut sessions = SessionStore::new(&clock, SessionPolicy::default());
+            let mut policy = Policy::new();
+            policy.define_role("admin", &[]).unwrap();
+            policy.grant("admin", Permission::new(Action::Admin, "*")).unwrap();
+            let mut two_factor = TotpStore::new(&clock, "RustByExample", TotpConfig::default());
+
+            policy.assign(&accounts, "jane", "admin").unwrap();
+            let provisioning = two_factor.enroll("jane").unwrap();
+            let key = base32_decode(&provisioning.secret_base32).unwrap();
+            two_factor.confirm("jane", &totp(&key, clock.now(), &TotpConfig::default())).unwrap();
+            let session = sessions.issue("jane");
+
+            let info = delete(&mut accounts, &mut sessions, &mut policy, &mut two_factor, "Jane").unwrap();
+            assert_eq!(info.name, "Jane Doe");
+            assert_eq!(delete(&mut accounts, &mut sessions, &mut policy, &mut two_factor, "jane").map(|_| ()),
+                       Err(AccountError::NotFound));
+            assert_eq!(policy.assign(&accounts, "jane", "admin"), Err(PolicyError::UnknownUser("jane".to_string())));
+
+            accounts.register("jane", Secret::from("not the same jane"), AccountInfo::new("Jane", "jane@example.org")).unwrap();
+            assert!(policy.effective_roles("jane").is_empty());
+            assert!(!policy.can("jane", Action::Admin, "accounts/j.everyman"));
+            assert!(!two_factor.is_enabled("jane"));
+            assert_eq!(sessions.validate(session.token.as_str()), Err(SessionError::Invalid));
+        }
+    }
+}
+
+mod set_algebra {
+    use std::collections::{HashMap, HashSet};
+    use std::fmt;
+    use std::fs;
+    use std::hash::{BuildHasher, Hash};
+    use std::path::Path;
+
+    // ---- 多个集合的运算 ----
+
+    // 结果的哈希器是 S::default()，所以 HashSet<T, FxBuildHasher> 之类的集合也能直接用
+    pub fn union_all<'a, T, S, I>(sets: I) -> HashSet<T, S>
+        where T: Eq + Hash + Clone + 'a, S: BuildHasher + Default + 'a, I: IntoIterator<Item = &'a HashSet<T, S>>
+    {
+        sets.into_iter().flatten().cloned().collect()
+    }
+
+    // 零个集合的交集没有定义（应该是全集），返回 None
+    pub fn intersection_all<'a, T, S, I>(sets: I) -> Option<HashSet<T, S>>
+        where T: Eq + Hash + Clone + 'a, S: BuildHasher + Default + 'a, I: IntoIterator<Item = &'a HashSet<T, S>>
+    {
+        let mut sets: Vec<&HashSet<T, S>> = sets.into_iter().collect();
+        // 从最小的集合开始，要检查的元素最少
+        sets.sort_by_key(|set| set.len());
+        let (smallest, rest) = sets.split_first()?;
+        Some(smallest.iter().filter(|x| rest.iter().all(|set| set.contains(*x))).cloned().collect())
+    }
+
+    // 属于 first 但不属于其余任何一个集合
+    pub fn difference_all<'a, T, S, I>(first: &HashSet<T, S>, rest: I) -> HashSet<T, S>
+        where T: Eq + Hash + Clone + 'a, S: BuildHasher + Default + 'a, I: IntoIterator<Item = &'a HashSet<T, S>>
+    {
+        let rest: Vec<&HashSet<T, S>> = rest.into_iter().collect();
+        first.iter().filter(|x| !rest.iter().any(|set| set.contains(*x))).cloned().collect()
+    }
+
+    // 多个集合的对称差：出现在奇数个集合里的元素
+    pub fn symmetric_difference_all<'a, T, S, I>(sets: I) -> HashSet<T, S>
+        where T: Eq + Hash + Clone + 'a, S: BuildHasher + Default + 'a, I: IntoIterator<Item = &'a HashSet<T, S>>
+    {
+        let mut result = HashSet::default();
+        for set in sets {
+            result = result.symmetric_difference(set).cloned().collect();
+        }
+        result
+    }
+
+    // 任意两个集合都没有公共元素
+    pub fn pairwise_disjoint<'a, T, S, I>(sets: I) -> bool
+        where T: Eq + Hash + 'a, S: BuildHasher + 'a, I: IntoIterator<Item = &'a HashSet<T, S>>
+    {
+        let mut seen = HashSet::new();
+        sets.into_iter().all(|set| set.iter().all(|x| seen.insert(x)))
+    }
+
+    // ---- 两个集合之间的关系 ----
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Relation {
+        Equal,
+        // 真子集
+        Subset,
+        // 真超集
+        Superset,
+        Disjoint,
+        // 有交集，但谁也不包含谁
+        Overlapping,
+    }
+
+    pub fn relation<T: Eq + Hash, S: BuildHasher>(a: &HashSet<T, S>, b: &HashSet<T, S>) -> Relation {
+        match (a.is_subset(b), b.is_subset(a)) {
+            (true, true) => Relation::Equal,
+            (true, false) => Relation::Subset,
+            (false, true) => Relation::Superset,
+            (false, false) if a.is_disjoint(b) => Relation::Disjoint,
+            (false, false) => Relation::Overlapping,
+        }
+    }
+
+    // ---- 幂集和笛卡尔积 ----
+
+    // 依次给出所有 2^n 个子集，第 k 个子集由 k 的二进制位决定包含哪些元素
+    pub struct PowerSet<'a, T> {
+        elements: Vec<&'a T>,
+        next: u64,
+        done: bool,
+    }
+
+    pub fn power_set<T, S>(set: &HashSet<T, S>) -> PowerSet<'_, T> {
+        assert!(set.len() < 64, "power set of {} elements is too large to enumerate", set.len());
+        PowerSet { elements: set.iter().collect(), next: 0, done: false }
+    }
+
+    impl<'a, T> Iterator for PowerSet<'a, T> {
+        type Item = Vec<&'a T>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            let mask = self.next;
+            let subset = self.elements.iter().enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, x)| *x)
+                .collect();
+            if mask == (1u64 << self.elements.len()) - 1 {
+                self.done = true;
+            } else {
+                self.next += 1;
+            }
+            Some(subset)
+        }
+    }
+
+    // 多个集合的笛卡尔积，像里程表一样从最后一位开始进位
+    pub struct CartesianProduct<'a, T> {
+        sets: Vec<Vec<&'a T>>,
+        indices: Vec<usize>,
+        done: bool,
+    }
+
+    pub fn cartesian_product<'a, T, S, I>(sets: I) -> CartesianProduct<'a, T>
+        where S: 'a, I: IntoIterator<Item = &'a HashSet<T, S>>
+    {
+        let sets: Vec<Vec<&T>> = sets.into_iter().map(|set| set.iter().collect()).collect();
+        // 任何一个集合为空，积就是空的；零个集合的积只有一个空元组
+        let done = sets.iter().any(Vec::is_empty);
+        CartesianProduct { indices: vec![0; sets.len()], sets, done }
+    }
+
+    impl<'a, T> Iterator for CartesianProduct<'a, T> {
+        type Item = Vec<&'a T>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            let item = self.indices.iter().zip(&self.sets).map(|(&i, set)| set[i]).collect();
+            self.done = true;
+            for position in (0..self.sets.len()).rev() {
+                self.indices[position] += 1;
+                if self.indices[position] < self.sets[position].len() {
+                    self.done = false;
+                    break;
+                }
+                self.indices[position] = 0;
+            }
+            Some(item)
+        }
+    }
+
+    // ---- 集合表达式 ----
+    // 运算符和 Python 的集合一样，优先级从高到低：- 差集，& 交集，^ 对称差，| 并集，同级从左往右算。
+    // 例如 (A | B) - C、A & B ^ C
+
+    #[derive(Debug, PartialEq)]
+    pub enum ExprError {
+        UnknownSet(String),
+        // 在第几个字符（从 0 开始）遇到了不认识或不该出现的东西
+        Unexpected { position: usize, found: String },
+        UnexpectedEnd,
+    }
+
+    impl fmt::Display for ExprError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ExprError::UnknownSet(name) => write!(f, "unknown set {:?}", name),
+                ExprError::Unexpected { position, found } => write!(f, "unexpected {:?} at position {}", found, position),
+                ExprError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Name(String),
+        Op(char),
+        Open,
+        Close,
+    }
+
+    fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+        let mut tokens = Vec::new();
+        let mut chars = input.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '|' | '&' | '-' | '^' => Token::Op(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut name = c.to_string();
+                    while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                        name.push(c);
+                        chars.next();
+                    }
+                    Token::Name(name)
+                }
+                c => return Err(ExprError::Unexpected { position, found: c.to_string() }),
+            };
+            tokens.push((position, token));
+        }
+        Ok(tokens)
+    }
+
+    #[derive(Debug)]
+    pub enum Expr {
+        Set(String),
+        Binary(char, Box<Expr>, Box<Expr>),
+    }
+
+    // 优先级爬升法
+    fn precedence(op: char) -> u8 {
+        match op {
+            '-' => 4,
+            '&' => 3,
+            '^' => 2,
+            _ => 1,
+        }
+    }
+
+    struct Parser {
+        tokens: Vec<(usize, Token)>,
+        position: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> Option<&Token> {
+            self.tokens.get(self.position).map(|(_, token)| token)
+        }
+
+        fn unexpected(&self) -> ExprError {
+            match self.tokens.get(self.position) {
+                Some((position, token)) => {
+                    let found = match token {
+                        Token::Name(name) => name.clone(),
+                        Token::Op(op) => op.to_string(),
+                        Token::Open => "(".to_string(),
+                        Token::Close => ")".to_string(),
+                    };
+                    ExprError::Unexpected { position: *position, found }
+                }
+                None => ExprError::UnexpectedEnd,
+            }
+        }
+
+        fn primary(&mut self) -> Result<Expr, ExprError> {
+            match self.peek().cloned() {
+                Some(Token::Name(name)) => {
+                    self.position += 1;
+                    Ok(Expr::Set(name))
+                }
+                Some(Token::Open) => {
+                    self.position += 1;
+                    let expr = self.binary(0)?;
+                    if self.peek() != Some(&Token::Close) {
+                        return Err(self.unexpected());
+                    }
+                    self.position += 1;
+                    Ok(expr)
+                }
+                _ => Err(self.unexpected()),
+            }
+        }
+
+        fn binary(&mut self, min_precedence: u8) -> Result<Expr, ExprError> {
+            let mut lhs = self.primary()?;
+            while let Some(Token::Op(op)) = self.peek().cloned() {
+                if precedence(op) <= min_precedence {
+                    break;
+                }
+                self.position += 1;
+                let rhs = self.binary(precedence(op))?;
+                lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+            }
+            Ok(lhs)
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser { tokens: tokenize(input)?, position: 0 };
+        let expr = parser.binary(0)?;
+        if parser.position < parser.tokens.len() {
+            return Err(parser.unexpected());
+        }
+        Ok(expr)
+    }
+
+    impl Expr {
+        pub fn eval<T: Eq + Hash + Clone>(&self, sets: &HashMap<String, HashSet<T>>) -> Result<HashSet<T>, ExprError> {
+            match self {
+                Expr::Set(name) => sets.get(name).cloned().ok_or_else(|| ExprError::UnknownSet(name.clone())),
+                Expr::Binary(op, lhs, rhs) => {
+                    let (lhs, rhs) = (lhs.eval(sets)?, rhs.eval(sets)?);
+                    Ok(match op {
+                        '|' => lhs.union(&rhs).cloned().collect(),
+                        '&' => lhs.intersection(&rhs).cloned().collect(),
+                        '-' => lhs.difference(&rhs).cloned().collect(),
+                        _ => lhs.symmetric_difference(&rhs).cloned().collect(),
+                    })
+                }
+            }
+        }
+    }
+
+    pub fn evaluate<T: Eq + Hash + Clone>(input: &str, sets: &HashMap<String, HashSet<T>>) -> Result<HashSet<T>, ExprError> {
+        parse(input)?.eval(sets)
+    }
+
+    // 从文件加载命名集合，每行一个：`名字 = 元素, 元素, ...`，# 开头是注释
+    pub fn load_sets<P: AsRef<Path>>(path: P) -> Result<HashMap<String, HashSet<String>>, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        parse_sets(&contents)
+    }
+
+    pub fn parse_sets(contents: &str) -> Result<HashMap<String, HashSet<String>>, String> {
+        let mut sets = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, elements) = line.split_once('=')
+                .ok_or_else(|| format!("line {}: expected `NAME = a, b, c`", index + 1))?;
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(format!("line {}: invalid set name {:?}", index + 1, name));
+            }
+            let elements = elements.split(',').map(str::trim).filter(|e| !e.is_empty()).map(String::from).collect();
+            if sets.insert(name.to_string(), elements).is_some() {
+                return Err(format!("line {}: set {} defined twice", index + 1, name));
+            }
+        }
+        Ok(sets)
+    }
+}
+
+mod bitset {
+    use std::collections::{BTreeSet, HashSet};
+    use std::fmt;
+    use std::hint::black_box;
+    use std::iter::FromIterator;
+    use std::time::{Duration, Instant};
+
+    const BITS: usize = u64::BITS as usize;
+
+    // 用位图表示的小整数集合：元素 x 存在当且仅当 words[x / 64] 的第 x % 64 位是 1。
+    // 接口尽量和 HashSet 保持一致，集合运算每次处理 64 个元素。
+    // 注意：元素类型有意从 HashSet<i32> 的 i32 改成了 u32。位图按值做下标，负数没有位置可放，
+    // 转换成 usize 会变成一个巨大的下标；用 u32 的话负数在 u32::try_from 时就会出错，
+    // 位图最多也只占 512 MiB，不会因为一个很大的值撑爆内存
+    #[derive(Clone, Default)]
+    pub struct BitSet {
+        words: Vec<u64>,
+        len: usize,
+    }
+
+    impl BitSet {
+        pub fn new() -> Self {
+            BitSet::default()
+        }
+
+        // 预先分配能放下 0..capacity 的空间
+        pub fn with_capacity(capacity: usize) -> Self {
+            BitSet { words: vec![0; capacity.div_ceil(BITS)], len: 0 }
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub fn insert(&mut self, value: u32) -> bool {
+            let value = value as usize;
+            let (word, mask) = (value / BITS, 1 << (value % BITS));
+            if word >= self.words.len() {
+                self.words.resize(word + 1, 0);
+            }
+            let added = self.words[word] & mask == 0;
+            self.words[word] |= mask;
+            self.len += added as usize;
+            added
+        }
+
+        pub fn contains(&self, value: &u32) -> bool {
+            let value = *value as usize;
+            self.words.get(value / BITS).is_some_and(|word| word & (1 << (value % BITS)) != 0)
+        }
+
+        pub fn remove(&mut self, value: &u32) -> bool {
+            let removed = self.contains(value);
+            if removed {
+                let value = *value as usize;
+                self.words[value / BITS] &= !(1 << (value % BITS));
+                self.len -= 1;
+            }
+            removed
+        }
+
+        pub fn clear(&mut self) {
+            self.words.clear();
+            self.len = 0;
+        }
+
+        pub fn iter(&self) -> Iter<'_> {
+            Iter { words: WordOp::new(&self.words, &[], |a, _| a), ..Iter::default() }
+        }
+
+        pub fn union<'a>(&'a self, other: &'a BitSet) -> Iter<'a> {
+            Iter { words: WordOp::new(&self.words, &other.words, |a, b| a | b), ..Iter::default() }
+        }
+
+        pub fn intersection<'a>(&'a self, other: &'a BitSet) -> Iter<'a> {
+            Iter { words: WordOp::new(&self.words, &other.words, |a, b| a & b), ..Iter::default() }
+        }
+
+        pub fn difference<'a>(&'a self, other: &'a BitSet) -> Iter<'a> {
+            Iter { words: WordOp::new(&self.words, &other.words, |a, b| a & !b), ..Iter::default() }
+        }
+
+        pub fn symmetric_difference<'a>(&'a self, other: &'a BitSet) -> Iter<'a> {
+            Iter { words: WordOp::new(&self.words, &other.words, |a, b| a ^ b), ..Iter::default() }
+        }
+
+        pub fn is_subset(&self, other: &BitSet) -> bool {
+            self.words.iter().enumerate().all(|(i, word)| word & !other.words.get(i).unwrap_or(&0) == 0)
+        }
+
+        pub fn is_superset(&self, other: &BitSet) -> bool {
+            other.is_subset(self)
+        }
+
+        pub fn is_disjoint(&self, other: &BitSet) -> bool {
+            self.words.iter().zip(&other.words).all(|(a, b)| a & b == 0)
+        }
+
+        // rank：集合里比 value 小的元素个数
+        pub fn rank(&self, value: u32) -> usize {
+            let value = value as usize;
+            let (word, bit) = (value / BITS, value % BITS);
+            let below: usize = self.words.iter().take(word).map(|w| w.count_ones() as usize).sum();
+            let partial = self.words.get(word).map_or(0, |w| (w & ((1 << bit) - 1)).count_ones() as usize);
+            below + partial
+        }
+
+        // select：第 k 小的元素（k 从 0 开始），是 rank 的逆运算
+        pub fn select(&self, mut k: usize) -> Option<u32> {
+            for (i, &word) in self.words.iter().enumerate() {
+                let ones = word.count_ones() as usize;
+                if k < ones {
+                    // 在这个字里逐个清掉最低位的 1
+                    let mut word = word;
+                    for _ in 0..k {
+                        word &= word - 1;
+                    }
+                    return Some((i * BITS) as u32 + word.trailing_zeros());
+                }
+                k -= ones;
+            }
+            None
+        }
+
+        // 占用的内存（字节）
+        pub fn memory(&self) -> usize {
+            self.words.capacity() * std::mem::size_of::<u64>()
+        }
+    }
+
+    // 两个位图逐字做运算，较短的一边视为补 0
+    #[derive(Default)]
+    struct WordOp<'a> {
+        lhs: &'a [u64],
+        rhs: &'a [u64],
+        op: Option<fn(u64, u64) -> u64>,
+        index: usize,
+    }
+
+    impl<'a> WordOp<'a> {
+        fn new(lhs: &'a [u64], rhs: &'a [u64], op: fn(u64, u64) -> u64) -> Self {
+            WordOp { lhs, rhs, op: Some(op), index: 0 }
+        }
+    }
+
+    impl Iterator for WordOp<'_> {
+        type Item = u64;
+
+        fn next(&mut self) -> Option<u64> {
+            if self.index >= self.lhs.len().max(self.rhs.len()) {
+                return None;
+            }
+            let word = |words: &[u64]| words.get(self.index).copied().unwrap_or(0);
+            let result = (self.op?)(word(self.lhs), word(self.rhs));
+            self.index += 1;
+            Some(result)
+        }
+    }
+
+    // 把一串字展开成元素：每次取出最低位的 1
+    #[derive(Default)]
+    pub struct Iter<'a> {
+        words: WordOp<'a>,
+        current: u64,
+        base: usize,
+    }
+
+    impl Iterator for Iter<'_> {
+        type Item = u32;
+
+        fn next(&mut self) -> Option<u32> {
+            while self.current == 0 {
+                self.base = self.words.index * BITS;
+                self.current = self.words.next()?;
+            }
+            let bit = self.current.trailing_zeros();
+            self.current &= self.current - 1;
+            Some(self.base as u32 + bit)
+        }
+    }
+
+    impl<'a> IntoIterator for &'a BitSet {
+        type Item = u32;
+        type IntoIter = Iter<'a>;
+
+        fn into_iter(self) -> Iter<'a> {
+            self.iter()
+        }
+    }
+
+    impl FromIterator<u32> for BitSet {
+        fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+            let mut set = BitSet::new();
+            set.extend(iter);
+            set
+        }
+    }
+
+    impl Extend<u32> for BitSet {
+        fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+            for value in iter {
+                self.insert(value);
+            }
+        }
+    }
+
+    // 末尾多出来的全 0 字不影响相等
+    impl PartialEq for BitSet {
+        fn eq(&self, other: &BitSet) -> bool {
+            self.len == other.len && self.is_subset(other)
+        }
+    }
+
+    impl Eq for BitSet {}
+
+    impl fmt::Debug for BitSet {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_set().entries(self.iter()).finish()
+        }
+    }
+
+    // ---- 和 HashSet<i32>、BTreeSet<i32> 的性能对比 ----
+    // 在 0..universe 里按密度随机取元素建两个集合，分别计时建集合、查找和求交集
+
+    pub struct BenchResult {
+        pub structure: &'static str,
+        pub build: Duration,
+        pub lookup: Duration,
+        pub intersection: Duration,
+    }
+
+    // 线性同余生成器，保证每种结构拿到的是同一批数
+    fn sample(universe: u32, density: f64, seed: u64) -> Vec<u32> {
+        let mut state = seed;
+        (0..universe).filter(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 11) as f64 / (1u64 << 53) as f64) < density
+        }).collect()
+    }
+
+    fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        let start = Instant::now();
+        let result = f();
+        (result, start.elapsed())
+    }
+
+    // universe 不能超过 i32::MAX，否则 i32 的集合放不下
+    pub fn benchmark(universe: u32, density: f64) -> Vec<BenchResult> {
+        assert!(i32::try_from(universe).is_ok(), "universe {} does not fit in i32", universe);
+        let (xs, ys) = (sample(universe, density, 1), sample(universe, density, 2));
+        let probes = 0..universe;
+        let mut results = Vec::new();
+
+        let ((a, b), build) = time(|| (xs.iter().copied().collect::<BitSet>(), ys.iter().copied().collect::<BitSet>()));
+        let (_, lookup) = time(|| probes.clone().filter(|x| a.contains(black_box(x))).count());
+        let (_, intersection) = time(|| black_box(a.intersection(&b).count()));
+        results.push(BenchResult { structure: "BitSet", build, lookup, intersection });
+
+        let to_i32 = |v: &Vec<u32>| v.iter().map(|&x| x as i32).collect::<Vec<i32>>();
+        let (xs, ys) = (to_i32(&xs), to_i32(&ys));
+        let probes = 0..universe as i32;
+        let ((a, b), build) = time(|| (xs.iter().copied().collect::<HashSet<i32>>(), ys.iter().copied().collect::<HashSet<i32>>()));
+        let (_, lookup) = time(|| probes.clone().filter(|x| a.contains(black_box(x))).count());
+        let (_, intersection) = time(|| black_box(a.intersection(&b).count()));
+        results.push(BenchResult { structure: "HashSet<i32>", build, lookup, intersection });
+
+        let ((a, b), build) = time(|| (xs.iter().copied().collect::<BTreeSet<i32>>(), ys.iter().copied().collect::<BTreeSet<i32>>()));
+        let (_, lookup) = time(|| probes.clone().filter(|x| a.contains(black_box(x))).count());
+        let (_, intersection) = time(|| black_box(a.intersection(&b).count()));
+        results.push(BenchResult { structure: "BTreeSet<i32>", build, lookup, intersection });
+
+        results
+    }
+}
+
+mod multiset {
+    use std::cmp::Reverse;
+    use std::collections::HashMap;
+    use std::collections::hash_map;
+    use std::fmt;
+    use std::hash::Hash;
+    use std::iter::{self, FromIterator};
+    use crate::hashers::{HashState, HasherKind};
+
+    // 多重集（bag）：和 HashSet 一样无序，但会记住每个元素出现了几次
+    #[derive(Clone, PartialEq, Eq)]
+    pub struct MultiSet<T: Eq + Hash> {
+        counts: HashMap<T, usize, HashState>,
+        // 所有元素的个数之和
+        len: usize,
+    }
+
+    impl<T: Eq + Hash> Default for MultiSet<T> {
+        fn default() -> Self {
+            MultiSet { counts: HashMap::default(), len: 0 }
+        }
+    }
+
+    impl<T: Eq + Hash> MultiSet<T> {
+        pub fn new() -> Self {
+            MultiSet::default()
+        }
+
+        pub fn with_hasher(kind: HasherKind) -> Self {
+            MultiSet { counts: HashMap::with_hasher(HashState::new(kind)), len: 0 }
+        }
+
+        pub fn hasher(&self) -> HasherKind {
+            self.counts.hasher().kind()
+        }
+
+        // 算上重复的元素个数
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        // 不同元素的个数
+        pub fn distinct(&self) -> usize {
+            self.counts.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub fn count(&self, value: &T) -> usize {
+            self.counts.get(value).copied().unwrap_or(0)
+        }
+
+        pub fn contains(&self, value: &T) -> bool {
+            self.counts.contains_key(value)
+        }
+
+        // 返回插入之前的个数
+        pub fn insert(&mut self, value: T) -> usize {
+            self.insert_many(value, 1)
+        }
+
+        pub fn insert_many(&mut self, value: T, n: usize) -> usize {
+            if n == 0 {
+                return self.count(&value);
+            }
+            self.len += n;
+            let count = self.counts.entry(value).or_insert(0);
+            *count += n;
+            *count - n
+        }
+
+        // 去掉一个，返回去掉之前的个数
+        pub fn remove(&mut self, value: &T) -> usize {
+            self.remove_many(value, 1)
+        }
+
+        // 最多去掉 n 个，个数变成 0 的元素不再保留
+        pub fn remove_many(&mut self, value: &T, n: usize) -> usize {
+            let before = self.count(value);
+            let removed = before.min(n);
+            if removed == before {
+                self.counts.remove(value);
+            } else if let Some(count) = self.counts.get_mut(value) {
+                *count -= removed;
+            }
+            self.len -= removed;
+            before
+        }
+
+        // 把某个元素全部去掉，返回去掉了几个
+        pub fn remove_all(&mut self, value: &T) -> usize {
+            let removed = self.counts.remove(value).unwrap_or(0);
+            self.len -= removed;
+            removed
+        }
+
+        // 每个不同的元素和它的个数
+        pub fn counts(&self) -> hash_map::Iter<'_, T, usize> {
+            self.counts.iter()
+        }
+
+        // 按个数展开：出现 3 次的元素会给出 3 次
+        pub fn iter(&self) -> impl Iterator<Item = &T> {
+            self.counts.iter().flat_map(|(value, &n)| iter::repeat_n(value, n))
+        }
+
+        // 出现次数最多的 n 个元素，次数相同时顺序不定
+        pub fn most_common(&self, n: usize) -> Vec<(&T, usize)> {
+            let mut counts: Vec<(&T, usize)> = self.counts.iter().map(|(value, &n)| (value, n)).collect();
+            counts.sort_by_key(|&(_, n)| Reverse(n));
+            counts.truncate(n);
+            counts
+        }
+
+        pub fn is_subset(&self, other: &MultiSet<T>) -> bool {
+            self.counts.iter().all(|(value, &n)| n <= other.count(value))
+        }
+    }
+
+    impl<T: Eq + Hash + Clone> MultiSet<T> {
+        // 逐个元素合并计数，结果里个数为 0 的元素会被丢掉
+        // 结果沿用 self 的哈希器
+        fn combine(&self, other: &MultiSet<T>, f: impl Fn(usize, usize) -> usize) -> MultiSet<T> {
+            let mut result = MultiSet::with_hasher(self.hasher());
+            for value in self.counts.keys().chain(other.counts.keys()) {
+                if !result.contains(value) {
+                    result.insert_many(value.clone(), f(self.count(value), other.count(value)));
+                }
+            }
+            result
+        }
+
+        // 并：取两边个数的较大值
+        pub fn union(&self, other: &MultiSet<T>) -> MultiSet<T> {
+            self.combine(other, usize::max)
+        }
+
+        // 和：个数相加
+        pub fn sum(&self, other: &MultiSet<T>) -> MultiSet<T> {
+            self.combine(other, |a, b| a + b)
+        }
+
+        // 交：取两边个数的较小值
+        pub fn intersection(&self, other: &MultiSet<T>) -> MultiSet<T> {
+            self.combine(other, usize::min)
+        }
+
+        // 差：个数相减，减到 0 为止
+        pub fn difference(&self, other: &MultiSet<T>) -> MultiSet<T> {
+            self.combine(other, usize::saturating_sub)
+        }
+    }
+
+    impl<T: Eq + Hash> FromIterator<T> for MultiSet<T> {
+        fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+            let mut set = MultiSet::new();
+            set.extend(iter);
+            set
+        }
+    }
+
+    // 从 (元素, 个数) 建立
+    impl<T: Eq + Hash> FromIterator<(T, usize)> for MultiSet<T> {
+        fn from_iter<I: IntoIterator<Item = (T, usize)>>(iter: I) -> Self {
+            let mut set = MultiSet::new();
+            for (value, n) in iter {
+                set.insert_many(value, n);
+            }
+            set
+        }
+    }
+
+    impl<T: Eq + Hash> Extend<T> for MultiSet<T> {
+        fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+            for value in iter {
+                self.insert(value);
+            }
+        }
+    }
+
+    impl<T: Eq + Hash + fmt::Debug> fmt::Debug for MultiSet<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_map().entries(self.counts.iter()).finish()
+        }
+    }
+}
+
+mod sketch {
+    use std::collections::hash_map::DefaultHasher;
+    use std::f64::consts::LN_2;
+    use std::hash::{Hash, Hasher};
+    use std::marker::PhantomData;
+
+    // 用两个不同种子的哈希值 h1、h2 模拟出 k 个哈希函数：h1 + i * h2（Kirsch-Mitzenmacher）。
+    // DefaultHasher::new() 的密钥是固定的，所以同一个元素每次得到的位置都一样
+    fn hash_pair<T: Hash + ?Sized>(value: &T) -> (u64, u64) {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            value.hash(&mut hasher);
+            hasher.finish()
+        };
+        // h2 是奇数，保证 k 个位置不会全都落在同一处
+        (hash(0), hash(1) | 1)
+    }
+
+    fn indexes<T: Hash + ?Sized>(value: &T, hashes: u32, slots: usize) -> impl Iterator<Item = usize> {
+        let (h1, h2) = hash_pair(value);
+        (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % slots as u64) as usize)
+    }
+
+    // 给定要放 n 个元素、希望误判率为 p，最优的位数 m 和哈希函数个数 k
+    fn optimal_size(expected_items: usize, fp_rate: f64) -> (usize, u32) {
+        assert!(fp_rate > 0.0 && fp_rate < 1.0, "false positive rate must be in (0, 1)");
+        let n = expected_items.max(1) as f64;
+        let m = (-n * fp_rate.ln() / (LN_2 * LN_2)).ceil();
+        let k = (m / n * LN_2).round().max(1.0);
+        (m as usize, k as u32)
+    }
+
+    // 放了 n 个元素之后理论上的误判率：(1 - e^(-kn/m))^k
+    fn false_positive_rate(items: usize, hashes: u32, slots: usize) -> f64 {
+        (1.0 - (-(hashes as f64) * items as f64 / slots as f64).exp()).powi(hashes as i32)
+    }
+
+    // 布隆过滤器：说“不在”一定不在，说“在”则有一定概率误判。不能删除元素
+    pub struct BloomFilter<T: ?Sized> {
+        bits: Vec<u64>,
+        slots: usize,
+        hashes: u32,
+        items: usize,
+        marker: PhantomData<fn(&T)>,
+    }
+
+    impl<T: Hash + ?Sized> BloomFilter<T> {
+        pub fn new(expected_items: usize, fp_rate: f64) -> Self {
+            let (slots, hashes) = optimal_size(expected_items, fp_rate);
+            BloomFilter { bits: vec![0; slots.div_ceil(64)], slots, hashes, items: 0, marker: PhantomData }
+        }
+
+        pub fn insert(&mut self, value: &T) {
+            for i in indexes(value, self.hashes, self.slots) {
+                self.bits[i / 64] |= 1 << (i % 64);
+            }
+            self.items += 1;
+        }
+
+        pub fn contains(&self, value: &T) -> bool {
+            indexes(value, self.hashes, self.slots).all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+        }
+
+        // 插入过的次数（重复插入也算）
+        pub fn len(&self) -> usize {
+            self.items
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.items == 0
+        }
+
+        pub fn bits(&self) -> usize {
+            self.slots
+        }
+
+        pub fn hashes(&self) -> u32 {
+            self.hashes
+        }
+
+        pub fn expected_fp_rate(&self) -> f64 {
+            false_positive_rate(self.items, self.hashes, self.slots)
+        }
+    }
+
+    // 计数布隆过滤器：每个位置换成一个计数器，于是可以删除。
+    // 计数器到 255 就不再变化，以免删除时把别的元素也删掉
+    pub struct CountingBloomFilter<T: ?Sized> {
+        counters: Vec<u8>,
+        hashes: u32,
+        items: usize,
+        marker: PhantomData<fn(&T)>,
+    }
+
+    impl<T: Hash + ?Sized> CountingBloomFilter<T> {
+        pub fn new(expected_items: usize, fp_rate: f64) -> Self {
+            let (slots, hashes) = optimal_size(expected_items, fp_rate);
+            CountingBloomFilter { counters: vec![0; slots], hashes, items: 0, marker: PhantomData }
+        }
+
+        pub fn insert(&mut self, value: &T) {
+            for i in indexes(value, self.hashes, self.counters.len()) {
+                self.counters[i] = self.counters[i].saturating_add(1);
+            }
+            self.items += 1;
+        }
+
+        // 只能删除插入过的元素；看起来不在的元素不会动任何计数器
+        pub fn remove(&mut self, value: &T) -> bool {
+            if !self.contains(value) {
+                return false;
+            }
+            for i in indexes(value, self.hashes, self.counters.len()) {
+                if self.counters[i] != u8::MAX {
+                    self.counters[i] -= 1;
+                }
+            }
+            self.items -= 1;
+            true
+        }
+
+        pub fn contains(&self, value: &T) -> bool {
+            self.count(value) > 0
+        }
+
+        // 元素被插入次数的上界
+        pub fn count(&self, value: &T) -> u8 {
+            indexes(value, self.hashes, self.counters.len()).map(|i| self.counters[i]).min().unwrap_or(0)
+        }
+
+        pub fn len(&self) -> usize {
+            self.items
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.items == 0
+        }
+
+        pub fn expected_fp_rate(&self) -> f64 {
+            false_positive_rate(self.items, self.hashes, self.counters.len())
+        }
+    }
+
+    // HyperLogLog：用 2^precision 个字节估计不同元素的个数，标准误差约为 1.04 / sqrt(2^precision)
+    pub struct HyperLogLog<T: ?Sized> {
+        precision: u32,
+        registers: Vec<u8>,
+        marker: PhantomData<fn(&T)>,
+    }
+
+    impl<T: Hash + ?Sized> HyperLogLog<T> {
+        pub fn new(precision: u32) -> Self {
+            assert!((4..=16).contains(&precision), "precision must be between 4 and 16");
+            HyperLogLog { precision, registers: vec![0; 1 << precision], marker: PhantomData }
+        }
+
+        // 高 precision 位选寄存器，其余的位里第一个 1 的位置越靠后，说明见过的不同元素越多
+        pub fn insert(&mut self, value: &T) {
+            let (hash, _) = hash_pair(value);
+            let index = (hash >> (64 - self.precision)) as usize;
+            let rest = hash << self.precision;
+            let rank = (rest.leading_zeros() + 1).min(64 - self.precision + 1) as u8;
+            self.registers[index] = self.registers[index].max(rank);
+        }
+
+        pub fn estimate(&self) -> f64 {
+            let m = self.registers.len() as f64;
+            let alpha = match self.registers.len() {
+                16 => 0.673,
+                32 => 0.697,
+                64 => 0.709,
+                _ => 0.7213 / (1.0 + 1.079 / m),
+            };
+            let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+            let raw = alpha * m * m / sum;
+            let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+            // 基数较小时改用线性计数，更准
+            if raw <= 2.5 * m && zeros > 0 {
+                m * (m / zeros as f64).ln()
+            } else {
+                raw
+            }
+        }
+
+        // 合并之后的估计值等于两边元素并集的估计值
+        pub fn merge(&mut self, other: &HyperLogLog<T>) {
+            assert_eq!(self.precision, other.precision, "cannot merge sketches of different precision");
+            for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+                *mine = (*mine).max(*theirs);
+            }
+        }
+
+        pub fn standard_error(&self) -> f64 {
+            1.04 / (self.registers.len() as f64).sqrt()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::HashSet;
+
+        // 布隆过滤器不会漏报，误报率应该接近设定的 1%；用精确的 HashSet 判断哪些是误报
+        #[test]
+        fn bloom_filter_has_no_false_negatives_and_few_false_positives() {
+            let members: HashSet<String> = (0..10_000).map(|i| format!("user-{}", i)).collect();
+            let mut bloom = BloomFilter::<str>::new(members.len(), 0.01);
+            assert!(bloom.is_empty());
+            for member in &members {
+                bloom.insert(member);
+            }
+            assert!(members.iter().all(|member| bloom.contains(member)));
+            let strangers: Vec<String> = (0..10_000).map(|i| format!("guest-{}", i)).collect();
+            let false_positives = strangers.iter().filter(|s| !members.contains(*s) && bloom.contains(s)).count();
+            let fp_rate = false_positives as f64 / strangers.len() as f64;
+            assert!(fp_rate < 2.0 * bloom.expected_fp_rate(), "{} vs {}", fp_rate, bloom.expected_fp_rate());
+            assert!(!bloom.is_empty() && bloom.len() == members.len());
+        }
+
+        // 删除之后就查不到了（除非恰好误报）；没插入过的元素删不掉
+        #[test]
+        fn counting_bloom_filter_supports_removal() {
+            let mut counting = CountingBloomFilter::<str>::new(1000, 0.01);
+            for i in 0..1000 {
+                counting.insert(&format!("user-{}", i));
+            }
+            counting.insert("user-7");
+            assert!(counting.count("user-7") >= 2);
+            for i in 0..500 {
+                assert!(counting.remove(&format!("user-{}", i)));
+            }
+            assert!(counting.contains("user-7") && counting.remove("user-7"));
+            let still_there = (500..1000).filter(|i| counting.contains(&format!("user-{}", i))).count();
+            let ghosts = (0..500).filter(|&i| i != 7 && counting.contains(&format!("user-{}", i))).count();
+            assert_eq!((counting.len(), still_there), (500, 500));
+            assert!(ghosts < 25 && !counting.is_empty(), "{} ghosts", ghosts);
+            assert!(!CountingBloomFilter::<str>::new(10, 0.01).remove("nobody"));
+        }
+
+        // 100000 次访问里有 30000 个不同的用户，误差应该在三倍标准误差以内
+        #[test]
+        fn hyperloglog_estimate_is_close_to_the_exact_count() {
+            let visits: Vec<u64> = (0..100_000u64).map(|i| i * 7919 % 30_000).collect();
+            let exact: HashSet<u64> = visits.iter().copied().collect();
+            let mut hll = HyperLogLog::<u64>::new(12);
+            for visit in &visits {
+                hll.insert(visit);
+            }
+            let error = (hll.estimate() - exact.len() as f64).abs() / exact.len() as f64;
+            assert!(error < 3.0 * hll.standard_error(), "error {}", error);
+        }
+
+        // 小基数时用线性计数，几乎是精确的
+        #[test]
+        fn hyperloglog_is_nearly_exact_for_small_sets() {
+            let mut small = HyperLogLog::<u64>::new(12);
+            (0..100).for_each(|x| small.insert(&x));
+            assert!((small.estimate() - 100.0).abs() < 3.0, "estimate {}", small.estimate());
+        }
+
+        // 合并两个草图相当于对并集计数：0..60000 和 30000..90000 的并集有 90000 个
+        #[test]
+        fn merged_hyperloglogs_count_the_union() {
+            let (mut left, mut right) = (HyperLogLog::<u64>::new(12), HyperLogLog::<u64>::new(12));
+            (0..60_000).for_each(|x| left.insert(&x));
+            (30_000..90_000).for_each(|x| right.insert(&x));
+            left.merge(&right);
+            assert!((left.estimate() - 90_000.0).abs() / 90_000.0 < 3.0 * left.standard_error(), "estimate {}", left.estimate());
+        }
+    }
+}
+
+mod hashers {
+    use std::collections::hash_map::{DefaultHasher, RandomState};
+    use std::collections::{HashMap, HashSet};
+    use std::fmt;
+    use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+    use std::hint::black_box;
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    // FxHash（rustc 内部用的哈希）：每次把 8 个字节异或进去再乘一个常数，非常快，
+    // 但没有密钥，别人可以构造出大量冲突的键（HashDoS），只适合键不受外部控制的场合
+    #[derive(Default, Clone, Copy)]
+    pub struct FxHasher {
+        hash: u64,
+    }
+
+    const FX_SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    impl FxHasher {
+        fn add(&mut self, word: u64) {
+            self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_SEED);
+        }
+    }
+
+    impl Hasher for FxHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            let mut chunks = bytes.chunks_exact(8);
+            for chunk in &mut chunks {
+                self.add(u64::from_le_bytes(chunk.try_into().unwrap()));
+            }
+            for &byte in chunks.remainder() {
+                self.add(byte as u64);
+            }
+        }
+
+        fn write_u8(&mut self, n: u8) {
+            self.add(n as u64);
+        }
+
+        fn write_u32(&mut self, n: u32) {
+            self.add(n as u64);
+        }
+
+        fn write_u64(&mut self, n: u64) {
+            self.add(n);
+        }
+
+        fn write_usize(&mut self, n: usize) {
+            self.add(n as u64);
+        }
+
+        fn finish(&self) -> u64 {
+            self.hash
+        }
+    }
+
+    // FNV-1a：逐字节异或再乘 FNV 质数。短键很快，长键比 Fx 慢；同样没有密钥
+    #[derive(Clone, Copy)]
+    pub struct FnvHasher(u64);
+
+    impl Default for FnvHasher {
+        fn default() -> Self {
+            FnvHasher(0xcbf2_9ce4_8422_2325)
+        }
+    }
+
+    impl Hasher for FnvHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.0 ^= byte as u64;
+                self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
+    }
+
+    pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+    pub type FnvBuildHasher = BuildHasherDefault<FnvHasher>;
+
+    // 运行时可以选的哈希算法，通讯录、账号和多重集都用它
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum HasherKind {
+        // 标准库默认的 SipHash-1-3，每个 HashMap 一个随机密钥
+        #[default]
+        Sip,
+        Fx,
+        Fnv,
+    }
+
+    impl HasherKind {
+        pub const ALL: [HasherKind; 3] = [HasherKind::Sip, HasherKind::Fx, HasherKind::Fnv];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                HasherKind::Sip => "sip",
+                HasherKind::Fx => "fx",
+                HasherKind::Fnv => "fnv",
+            }
+        }
+
+        // 键来自外部（用户名、网络请求）时应该选能抵御 HashDoS 的
+        pub fn resists_hashdos(self) -> bool {
+            self == HasherKind::Sip
+        }
+
+        pub fn notes(self) -> &'static str {
+            match self {
+                HasherKind::Sip => "keyed with a random per-map seed; safe for attacker-controlled keys",
+                HasherKind::Fx => "unkeyed multiply-rotate; keys differing only in high bits collide in the low bits",
+                HasherKind::Fnv => "unkeyed byte-at-a-time; collisions can be searched for offline",
+            }
+        }
+    }
+
+    impl fmt::Display for HasherKind {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.pad(self.name())
+        }
+    }
+
+    impl FromStr for HasherKind {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            HasherKind::ALL.into_iter()
+                .find(|kind| kind.name().eq_ignore_ascii_case(s))
+                .ok_or_else(|| format!("unknown hasher {:?} (expected sip, fx or fnv)", s))
+        }
+    }
+
+    // 按 HasherKind 分派的 BuildHasher。默认是 SipHash，和不指定哈希器的 HashMap 一样
+    #[derive(Debug, Clone, Default)]
+    pub struct HashState {
+        kind: HasherKind,
+        sip: RandomState,
+    }
+
+    impl HashState {
+        pub fn new(kind: HasherKind) -> Self {
+            HashState { kind, sip: RandomState::new() }
+        }
+
+        pub fn kind(&self) -> HasherKind {
+            self.kind
+        }
+    }
+
+    pub enum AnyHasher {
+        Sip(DefaultHasher),
+        Fx(FxHasher),
+        Fnv(FnvHasher),
+    }
+
+    impl Hasher for AnyHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            match self {
+                AnyHasher::Sip(h) => h.write(bytes),
+                AnyHasher::Fx(h) => h.write(bytes),
+                AnyHasher::Fnv(h) => h.write(bytes),
+            }
+        }
+
+        fn write_u8(&mut self, n: u8) {
+            match self {
+                AnyHasher::Sip(h) => h.write_u8(n),
+                AnyHasher::Fx(h) => h.write_u8(n),
+                AnyHasher::Fnv(h) => h.write_u8(n),
+            }
+        }
+
+        fn write_u32(&mut self, n: u32) {
+            match self {
+                AnyHasher::Sip(h) => h.write_u32(n),
+                AnyHasher::Fx(h) => h.write_u32(n),
+                AnyHasher::Fnv(h) => h.write_u32(n),
+            }
+        }
+
+        fn write_u64(&mut self, n: u64) {
+            match self {
+                AnyHasher::Sip(h) => h.write_u64(n),
+                AnyHasher::Fx(h) => h.write_u64(n),
+                AnyHasher::Fnv(h) => h.write_u64(n),
+            }
+        }
+
+        fn write_usize(&mut self, n: usize) {
+            match self {
+                AnyHasher::Sip(h) => h.write_usize(n),
+                AnyHasher::Fx(h) => h.write_usize(n),
+                AnyHasher::Fnv(h) => h.write_usize(n),
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            match self {
+                AnyHasher::Sip(h) => h.finish(),
+                AnyHasher::Fx(h) => h.finish(),
+                AnyHasher::Fnv(h) => h.finish(),
+            }
+        }
+    }
+
+    impl BuildHasher for HashState {
+        type Hasher = AnyHasher;
+
+        fn build_hasher(&self) -> AnyHasher {
+            match self.kind {
+                HasherKind::Sip => AnyHasher::Sip(self.sip.build_hasher()),
+                HasherKind::Fx => AnyHasher::Fx(FxHasher::default()),
+                HasherKind::Fnv => AnyHasher::Fnv(FnvHasher::default()),
+            }
+        }
+    }
+
+    // ---- 性能对比 ----
+    // 每种哈希器分别用整数键和字符串键插入 n 个、再查找 n 次（一半命中）。
+    // 最后一列是 HashDoS 场景：n 个只有高 32 位不同的整数键，对 Fx 来说低位全是 0，全挤在同一串桶里
+
+    pub struct BenchResult {
+        pub hasher: HasherKind,
+        pub int_insert: Duration,
+        pub int_lookup: Duration,
+        pub str_insert: Duration,
+        pub str_lookup: Duration,
+        pub adversarial_insert: Duration,
+    }
+
+    fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        let start = Instant::now();
+        let result = f();
+        (result, start.elapsed())
+    }
+
+    fn run<S: BuildHasher + Default>(n: u64, adversarial: u64) -> [Duration; 5] {
+        let (ints, int_insert) = time(|| (0..n).map(|i| (i.wrapping_mul(0x9e37_79b9_7f4a_7c15), i)).collect::<HashMap<u64, u64, S>>());
+        let (_, int_lookup) = time(|| (0..n * 2).filter(|i| ints.contains_key(black_box(&i.wrapping_mul(0x9e37_79b9_7f4a_7c15)))).count());
+        let keys: Vec<String> = (0..n).map(|i| format!("user-{}@example.com", i)).collect();
+        let (strings, str_insert) = time(|| keys.iter().map(String::as_str).collect::<HashSet<&str, S>>());
+        let (_, str_lookup) = time(|| keys.iter().filter(|k| strings.contains(black_box(k.as_str()))).count());
+        let (_, adversarial_insert) = time(|| (0..adversarial).map(|i| i << 32).collect::<HashSet<u64, S>>());
+        [int_insert, int_lookup, str_insert, str_lookup, adversarial_insert]
+    }
+
+    pub fn benchmark(n: u64, adversarial: u64) -> Vec<BenchResult> {
+        HasherKind::ALL.into_iter().map(|hasher| {
+            let [int_insert, int_lookup, str_insert, str_lookup, adversarial_insert] = match hasher {
+                HasherKind::Sip => run::<RandomState>(n, adversarial),
+                HasherKind::Fx => run::<FxBuildHasher>(n, adversarial),
+                HasherKind::Fnv => run::<FnvBuildHasher>(n, adversarial),
+            };
+            BenchResult { hasher, int_insert, int_lookup, str_insert, str_lookup, adversarial_insert }
+        }).collect()
+    }
+
+    // 一组键的哈希值低 bits 位一共有几种取值。HashMap 用低位选桶，取值越少冲突越多
+    pub fn distinct_low_bits<S: BuildHasher, T: Hash>(state: &S, keys: impl IntoIterator<Item = T>, bits: u32) -> usize {
+        keys.into_iter().map(|key| state.hash_one(key) & ((1 << bits) - 1)).collect::<HashSet<u64>>().len()
+    }
+}
+
+mod robin_hood {
+    use std::borrow::Borrow;
+    use std::collections::hash_map::RandomState;
+    use std::fmt;
+    use std::hash::{BuildHasher, Hash};
+    use std::mem;
+
+    // 手写的开放寻址散列表，用来说明 HashMap 内部大概是怎么工作的。
+    //      开放寻址：所有键值对直接放在一个数组里，位置冲突就往后找空位（线性探测）
+    //      Robin Hood：插入时如果当前位置的元素离自己的理想位置更近（更“富”），就把位置让给
+    //          离理想位置更远的新元素（“劫富济贫”），于是所有元素的探测距离都比较平均，
+    //          查找时一旦遇到比自己更“富”的元素就可以断定键不存在
+    //      删除：把后面的元素依次往前挪一格（backward shift），不需要墓碑
+    // 元素个数超过容量的 7/8 时容量翻倍，删除后少于 1/4 时容量减半
+    const MIN_CAPACITY: usize = 8;
+
+    struct Slot<K, V> {
+        hash: u64,
+        key: K,
+        value: V,
+    }
+
+    pub struct RobinHoodMap<K, V, S = RandomState> {
+        slots: Vec<Option<Slot<K, V>>>,
+        len: usize,
+        hasher: S,
+        grows: usize,
+        shrinks: usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Stats {
+        pub len: usize,
+        pub capacity: usize,
+        pub load_factor: f64,
+        // 元素离理想位置的最大距离和平均距离
+        pub max_probe: usize,
+        pub mean_probe: f64,
+        pub grows: usize,
+        pub shrinks: usize,
+    }
+
+    impl fmt::Display for Stats {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{} / {} slots (load {:.2}), probe max {} mean {:.2}, grown {}x, shrunk {}x",
+                   self.len, self.capacity, self.load_factor, self.max_probe, self.mean_probe, self.grows, self.shrinks)
+        }
+    }
+
+    impl<K, V> RobinHoodMap<K, V, RandomState> {
+        pub fn new() -> Self {
+            RobinHoodMap::with_hasher(RandomState::new())
+        }
+    }
+
+    impl<K, V> Default for RobinHoodMap<K, V, RandomState> {
+        fn default() -> Self {
+            RobinHoodMap::new()
+        }
+    }
+
+    // 元素从理想位置 hash & mask 往后走了几格
+    fn distance(hash: u64, index: usize, mask: usize) -> usize {
+        index.wrapping_sub(hash as usize) & mask
+    }
+
+    impl<K, V, S> RobinHoodMap<K, V, S> {
+        // 第一次插入时才分配
+        pub fn with_hasher(hasher: S) -> Self {
+            RobinHoodMap { slots: Vec::new(), len: 0, hasher, grows: 0, shrinks: 0 }
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.slots.len()
+        }
+
+        pub fn clear(&mut self) {
+            self.slots.clear();
+            self.len = 0;
+        }
+
+        // 按数组里的顺序，也就是没有确定的顺序
+        pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+            self.slots.iter().flatten().map(|slot| (&slot.key, &slot.value))
+        }
+
+        pub fn stats(&self) -> Stats {
+            let mask = self.capacity().wrapping_sub(1);
+            let probes: Vec<usize> = self.slots.iter().enumerate()
+                .filter_map(|(i, slot)| slot.as_ref().map(|slot| distance(slot.hash, i, mask)))
+                .collect();
+            Stats {
+                len: self.len,
+                capacity: self.capacity(),
+                load_factor: if self.slots.is_empty() { 0.0 } else { self.len as f64 / self.capacity() as f64 },
+                max_probe: probes.iter().copied().max().unwrap_or(0),
+                mean_probe: if probes.is_empty() { 0.0 } else { probes.iter().sum::<usize>() as f64 / probes.len() as f64 },
+                grows: self.grows,
+                shrinks: self.shrinks,
+            }
+        }
+
+        // 换到新容量，所有元素重新放一遍（哈希值存着，不用重新计算）
+        fn resize(&mut self, capacity: usize) {
+            let old = mem::replace(&mut self.slots, (0..capacity).map(|_| None).collect());
+            for slot in old.into_iter().flatten() {
+                self.place(slot);
+            }
+        }
+
+        // 放入一个确定不存在的键，返回它最终所在的位置。调用前要保证有空位
+        fn place(&mut self, mut slot: Slot<K, V>) -> usize {
+            let mask = self.capacity() - 1;
+            let (mut index, mut probe) = (slot.hash as usize & mask, 0);
+            let mut placed = None;
+            loop {
+                match &mut self.slots[index] {
+                    empty @ None => {
+                        *empty = Some(slot);
+                        return placed.unwrap_or(index);
+                    }
+                    Some(resident) => {
+                        let resident_probe = distance(resident.hash, index, mask);
+                        if resident_probe < probe {
+                            // 劫富济贫：接着替被换出来的元素找位置
+                            mem::swap(resident, &mut slot);
+                            placed.get_or_insert(index);
+                            probe = resident_probe;
+                        }
+                    }
+                }
+                index = (index + 1) & mask;
+                probe += 1;
+            }
+        }
+
+        fn take(&mut self, index: usize) -> Slot<K, V> {
+            let mask = self.capacity() - 1;
+            let removed = self.slots[index].take().unwrap();
+            // 后面离理想位置有距离的元素依次往前挪
+            let mut hole = index;
+            loop {
+                let next = (hole + 1) & mask;
+                match &self.slots[next] {
+                    Some(slot) if distance(slot.hash, next, mask) > 0 => {
+                        self.slots[hole] = self.slots[next].take();
+                        hole = next;
+                    }
+                    _ => break,
+                }
+            }
+            self.len -= 1;
+            removed
+        }
+    }
+
+    impl<K: Eq + Hash, V, S: BuildHasher> RobinHoodMap<K, V, S> {
+        fn find<Q>(&self, hash: u64, key: &Q) -> Option<usize>
+            where K: Borrow<Q>, Q: Eq + ?Sized
+        {
+            if self.len == 0 {
+                return None;
+            }
+            let mask = self.capacity() - 1;
+            let mut index = hash as usize & mask;
+            for probe in 0.. {
+                match &self.slots[index] {
+                    None => return None,
+                    // 遇到比要找的键离理想位置更近的元素，说明要找的键不存在
+                    Some(slot) if distance(slot.hash, index, mask) < probe => return None,
+                    Some(slot) if slot.hash == hash && slot.key.borrow() == key => return Some(index),
+                    Some(_) => index = (index + 1) & mask,
+                }
+            }
+            unreachable!()
+        }
+
+        fn reserve_one(&mut self) {
+            if self.slots.is_empty() {
+                self.resize(MIN_CAPACITY);
+                self.grows += 1;
+            } else if (self.len + 1) * 8 > self.capacity() * 7 {
+                self.resize(self.capacity() * 2);
+                self.grows += 1;
+            }
+        }
+
+        fn shrink_if_sparse(&mut self) {
+            if self.capacity() > MIN_CAPACITY && self.len * 4 < self.capacity() {
+                self.resize(self.capacity() / 2);
+                self.shrinks += 1;
+            }
+        }
+
+        // 与 HashMap::insert 一致：键已存在时替换值并返回旧值
+        pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+            let hash = self.hasher.hash_one(&key);
+            if let Some(index) = self.find(hash, &key) {
+                return Some(mem::replace(&mut self.slots[index].as_mut().unwrap().value, value));
+            }
+            self.reserve_one();
+            self.place(Slot { hash, key, value });
+            self.len += 1;
+            None
+        }
+
+        pub fn get<Q>(&self, key: &Q) -> Option<&V>
+            where K: Borrow<Q>, Q: Eq + Hash + ?Sized
+        {
+            let index = self.find(self.hasher.hash_one(key), key)?;
+            self.slots[index].as_ref().map(|slot| &slot.value)
+        }
+
+        pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+            where K: Borrow<Q>, Q: Eq + Hash + ?Sized
+        {
+            let index = self.find(self.hasher.hash_one(key), key)?;
+            self.slots[index].as_mut().map(|slot| &mut slot.value)
+        }
+
+        pub fn contains_key<Q>(&self, key: &Q) -> bool
+            where K: Borrow<Q>, Q: Eq + Hash + ?Sized
+        {
+            self.find(self.hasher.hash_one(key), key).is_some()
+        }
+
+        pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+            where K: Borrow<Q>, Q: Eq + Hash + ?Sized
+        {
+            let index = self.find(self.hasher.hash_one(key), key)?;
+            let removed = self.take(index);
+            self.shrink_if_sparse();
+            Some(removed.value)
+        }
+
+        pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+            let hash = self.hasher.hash_one(&key);
+            match self.find(hash, &key) {
+                Some(index) => Entry::Occupied(OccupiedEntry { map: self, index }),
+                None => Entry::Vacant(VacantEntry { map: self, hash, key }),
+            }
+        }
+    }
+
+    // 和 std::collections::hash_map::Entry 一样的用法
+    pub enum Entry<'a, K, V, S> {
+        Occupied(OccupiedEntry<'a, K, V, S>),
+        Vacant(VacantEntry<'a, K, V, S>),
+    }
+
+    pub struct OccupiedEntry<'a, K, V, S> {
+        map: &'a mut RobinHoodMap<K, V, S>,
+        index: usize,
+    }
+
+    pub struct VacantEntry<'a, K, V, S> {
+        map: &'a mut RobinHoodMap<K, V, S>,
+        hash: u64,
+        key: K,
+    }
+
+    impl<'a, K: Eq + Hash, V, S: BuildHasher> Entry<'a, K, V, S> {
+        pub fn key(&self) -> &K {
+            match self {
+                Entry::Occupied(entry) => entry.key(),
+                Entry::Vacant(entry) => &entry.key,
+            }
+        }
+
+        pub fn or_insert(self, default: V) -> &'a mut V {
+            self.or_insert_with(|| default)
+        }
+
+        pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+            match self {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(default()),
+            }
+        }
+
+        pub fn or_default(self) -> &'a mut V where V: Default {
+            self.or_insert_with(V::default)
+        }
+
+        pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+            if let Entry::Occupied(entry) = &mut self {
+                f(entry.get_mut());
+            }
+            self
+        }
+    }
+
+    impl<'a, K: Eq + Hash, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
+        fn slot(&self) -> &Slot<K, V> {
+            self.map.slots[self.index].as_ref().unwrap()
+        }
+
+        pub fn key(&self) -> &K {
+            &self.slot().key
+        }
+
+        pub fn get(&self) -> &V {
+            &self.slot().value
+        }
+
+        pub fn get_mut(&mut self) -> &mut V {
+            &mut self.map.slots[self.index].as_mut().unwrap().value
+        }
+
+        pub fn into_mut(self) -> &'a mut V {
+            &mut self.map.slots[self.index].as_mut().unwrap().value
+        }
+
+        pub fn insert(&mut self, value: V) -> V {
+            mem::replace(self.get_mut(), value)
+        }
+
+        pub fn remove(self) -> V {
+            let removed = self.map.take(self.index);
+            self.map.shrink_if_sparse();
+            removed.value
+        }
+    }
+
+    impl<'a, K: Eq + Hash, V, S: BuildHasher> VacantEntry<'a, K, V, S> {
+        pub fn insert(self, value: V) -> &'a mut V {
+            let map = self.map;
+            map.reserve_one();
+            let index = map.place(Slot { hash: self.hash, key: self.key, value });
+            map.len += 1;
+            &mut map.slots[index].as_mut().unwrap().value
+        }
+    }
+
+    impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for RobinHoodMap<K, V, S> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_map().entries(self.iter()).finish()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::HashMap;
+        use crate::hashers::FxBuildHasher;
+
+        // 差分测试：同一串随机操作同时作用在 RobinHoodMap 和 std 的 HashMap 上，每一步结果都要一样。
+        // 键只取 300 个，这样插入、覆盖和删除都会频繁发生，容量也会反复增长和缩小
+        fn differential<S: BuildHasher>(mut ours: RobinHoodMap<u64, u64, S>, key_of: fn(u64) -> u64) -> Stats {
+            let mut theirs: HashMap<u64, u64> = HashMap::new();
+            let mut seed = 0x2545_f491_4f6c_dd1du64;
+            let mut next = || { seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17; seed };
+            for step in 0..50_000 {
+                let (op, key, value) = (next() % 100, key_of(next() % 300), next());
+                // 前半段插入多、后半段删除多
+                let (inserts, removes) = if step < 25_000 { (50, 25) } else { (5, 70) };
+                match op {
+                    op if op < inserts => assert_eq!(ours.insert(key, value), theirs.insert(key, value)),
+                    op if op < inserts + removes => assert_eq!(ours.remove(&key), theirs.remove(&key)),
+                    op if op < inserts + removes + 10 => {
+                        *ours.entry(key).or_insert(0) += value % 10;
+                        *theirs.entry(key).or_insert(0) += value % 10;
+                    }
+                    _ => assert_eq!(ours.get(&key), theirs.get(&key)),
+                }
+                assert_eq!(ours.len(), theirs.len());
+            }
+            assert!(theirs.iter().all(|(k, v)| ours.get(k) == Some(v)));
+            assert!(ours.iter().all(|(k, v)| theirs.get(k) == Some(v)));
+            ours.stats()
+        }
+
+        #[test]
+        fn random_operations_match_std_hash_map() {
+            let stats = differential(RobinHoodMap::new(), |k| k);
+            assert!(stats.grows > 0 && stats.shrinks > 0, "{}", stats);
+        }
+
+        // 用 Fx 加上低位全相同的键，所有键都挤在同一处，探测链很长，
+        // 专门检验 Robin Hood 的换位和删除时的往前挪
+        #[test]
+        fn colliding_keys_match_std_hash_map() {
+            let stats = differential(RobinHoodMap::with_hasher(FxBuildHasher::default()), |k| k << 32);
+            assert!(stats.max_probe > 10, "{}", stats);
+        }
+
+        #[test]
+        fn entry_api_behaves_like_hash_map() {
+            let mut colors: RobinHoodMap<String, i32> = RobinHoodMap::new();
+            assert_eq!(colors.capacity(), 0);
+            assert_eq!(colors.insert(String::from("Blue"), 10), None);
+            assert_eq!(colors.insert(String::from("Blue"), 25), Some(10));
+            *colors.entry(String::from("Yellow")).or_insert(50) += 1;
+            colors.entry(String::from("Blue")).and_modify(|v| *v *= 2).or_insert(0);
+            // 和 HashMap 一样可以用 &str 查 String 键
+            assert_eq!((colors.get("Blue"), colors.get("Yellow"), colors.get("Red")), (Some(&50), Some(&51), None));
+            if let Entry::Occupied(mut entry) = colors.entry(String::from("Yellow")) {
+                assert_eq!((entry.key().as_str(), *entry.get()), ("Yellow", 51));
+                assert_eq!(entry.insert(52), 51);
+                assert_eq!(entry.remove(), 52);
+            } else {
+                panic!("Yellow should be occupied");
+            }
+            let red = colors.entry(String::from("Red"));
+            assert_eq!(red.key(), "Red");
+            assert_eq!(*red.or_default(), 0);
+            assert_eq!(colors.remove("Red"), Some(0));
+            assert!(!colors.contains_key("Yellow") && colors.len() == 1);
+        }
+
+        // 负载因子不超过 7/8；删掉大部分元素后容量会缩小，剩下的元素还在
+        #[test]
+        fn grows_and_shrinks_with_the_number_of_entries() {
+            let mut squares: RobinHoodMap<u32, u32> = RobinHoodMap::default();
+            for i in 0..1000 {
+                squares.insert(i, i * i);
+            }
+            let grown = squares.stats();
+            assert!(grown.load_factor <= 0.875 && grown.capacity == 2048, "{}", grown);
+            for i in 0..990 {
+                assert_eq!(squares.remove(&i), Some(i * i));
+            }
+            let shrunk = squares.stats();
+            assert!(shrunk.shrinks > 0 && shrunk.capacity < grown.capacity, "{}", shrunk);
+            assert!((990..1000).all(|i| squares.get(&i) == Some(&(i * i))));
+            *squares.get_mut(&999).unwrap() = 0;
+            assert_eq!(squares.iter().map(|(_, v)| *v as u64).sum::<u64>(), (990..999u64).map(|i| i * i).sum());
+            squares.clear();
+            assert!(squares.is_empty() && squares.get(&995).is_none());
+        }
+    }
+}
+
+mod ordered_float {
+    use std::cmp::Ordering;
+    use std::fmt;
+    use std::hash::{Hash, Hasher};
+
+    // f32/f64 不能当 HashMap 的键，因为 NaN != NaN（违反 Eq 的自反性），而 0.0 == -0.0 但两者的位不同
+    // （直接对位做 Hash 会违反“相等则哈希相等”）。这里的包装类型把这两种情况的规则定死：
+    //
+    //      类型           NaN                                  ±0
+    //      OrderedFloat   所有 NaN 彼此相等，比任何数都大        0.0 和 -0.0 相等，哈希相同
+    //      NotNan         构造时就拒绝                         0.0 和 -0.0 相等，哈希相同
+    //      Quantized      构造时拒绝（无穷大也拒绝）             落在同一个桶
+    //
+    // 如果需要区分 -0.0 和 0.0、区分不同的 NaN，可以直接对 to_bits() 做键，排序用 f64::total_cmp
+
+    // 参与 Eq 和 Hash 的位：所有 NaN 统一成一个，-0.0 统一成 0.0
+    fn canonical_bits(value: f64) -> u64 {
+        if value.is_nan() {
+            f64::NAN.to_bits()
+        } else if value == 0.0 {
+            0
+        } else {
+            value.to_bits()
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct OrderedFloat(pub f64);
+
+    impl OrderedFloat {
+        pub fn into_inner(self) -> f64 {
+            self.0
+        }
+    }
+
+    impl PartialEq for OrderedFloat {
+        fn eq(&self, other: &Self) -> bool {
+            canonical_bits(self.0) == canonical_bits(other.0)
+        }
+    }
+
+    impl Eq for OrderedFloat {}
+
+    impl PartialOrd for OrderedFloat {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for OrderedFloat {
+        fn cmp(&self, other: &Self) -> Ordering {
+            match (self.0.is_nan(), other.0.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // 没有 NaN 时 partial_cmp 一定有结果，而且 -0.0 和 0.0 比较结果是 Equal
+                (false, false) => self.0.partial_cmp(&other.0).unwrap(),
+            }
+        }
+    }
+
+    impl Hash for OrderedFloat {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            canonical_bits(self.0).hash(state);
+        }
+    }
+
+    impl From<f64> for OrderedFloat {
+        fn from(value: f64) -> Self {
+            OrderedFloat(value)
+        }
+    }
+
+    impl fmt::Display for OrderedFloat {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Display::fmt(&self.0, f)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FloatError {
+        NaN,
+        // Quantized 要求有限值，量化步长也必须是正的有限数
+        NotFinite,
+        InvalidTolerance,
+    }
+
+    impl fmt::Display for FloatError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                FloatError::NaN => write!(f, "value is NaN"),
+                FloatError::NotFinite => write!(f, "value is not finite or out of range"),
+                FloatError::InvalidTolerance => write!(f, "tolerance must be positive and finite"),
+            }
+        }
+    }
+
+    // 保证不是 NaN 的 f64，比较和排序就是普通的数值比较
+    #[derive(Debug, Clone, Copy)]
+    pub struct NotNan(f64);
+
+    impl NotNan {
+        pub fn new(value: f64) -> Result<NotNan, FloatError> {
+            if value.is_nan() { Err(FloatError::NaN) } else { Ok(NotNan(value)) }
+        }
+
+        pub fn into_inner(self) -> f64 {
+            self.0
+        }
+    }
+
+    impl TryFrom<f64> for NotNan {
+        type Error = FloatError;
+
+        fn try_from(value: f64) -> Result<Self, Self::Error> {
+            NotNan::new(value)
+        }
+    }
+
+    impl PartialEq for NotNan {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for NotNan {}
+
+    impl PartialOrd for NotNan {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for NotNan {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.partial_cmp(&other.0).unwrap()
+        }
+    }
+
+    impl Hash for NotNan {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            canonical_bits(self.0).hash(state);
+        }
+    }
+
+    impl fmt::Display for NotNan {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Display::fmt(&self.0, f)
+        }
+    }
+
+    // 量化的键：把数轴按 tolerance 分成一个个桶，value 四舍五入到最近的桶。
+    // 相差不到 tolerance / 2 的两个值不一定在同一个桶（可能正好跨过边界），
+    // 所以按“附近”查找时要把相邻的桶也查一遍，见 neighbors
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct Quantized(i64);
+
+    impl Quantized {
+        pub fn new(value: f64, tolerance: f64) -> Result<Quantized, FloatError> {
+            if !(tolerance.is_finite() && tolerance > 0.0) {
+                return Err(FloatError::InvalidTolerance);
+            }
+            if value.is_nan() {
+                return Err(FloatError::NaN);
+            }
+            let bucket = (value / tolerance).round();
+            // 超出 i64 范围的也当作无穷大拒绝，免得 as 转换悄悄饱和
+            if !bucket.is_finite() || bucket.abs() >= i64::MAX as f64 {
+                return Err(FloatError::NotFinite);
+            }
+            Ok(Quantized(bucket as i64))
+        }
+
+        pub fn bucket(self) -> i64 {
+            self.0
+        }
+
+        // 桶的中心值
+        pub fn center(self, tolerance: f64) -> f64 {
+            self.0 as f64 * tolerance
+        }
+
+        // 自己和左右两个桶
+        pub fn neighbors(self) -> [Quantized; 3] {
+            [Quantized(self.0.saturating_sub(1)), self, Quantized(self.0.saturating_add(1))]
+        }
+    }
+}
+
+mod grouping {
+    use std::cmp::Ordering;
+    use std::collections::{BinaryHeap, HashMap};
+    use std::hash::Hash;
+
+    // 几个常见的分组统计，都是 entry API 的一行用法：
+    //      entry(key).or_insert(0) += 1            计数
+    //      entry(key).or_default().push(item)      分组
+    //      entry(key).or_insert_with(init)         折叠
+    // 只需要查一次哈希表，比先 get 再 insert 少一次查找
+
+    pub fn count_by<T, K, I, F>(items: I, mut key: F) -> HashMap<K, usize>
+        where I: IntoIterator<Item = T>, K: Eq + Hash, F: FnMut(&T) -> K
+    {
+        let mut counts = HashMap::new();
+        for item in items {
+            *counts.entry(key(&item)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // 每组里的元素保持原来的顺序
+    pub fn group_by<T, K, I, F>(items: I, mut key: F) -> HashMap<K, Vec<T>>
+        where I: IntoIterator<Item = T>, K: Eq + Hash, F: FnMut(&T) -> K
+    {
+        let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+        for item in items {
+            groups.entry(key(&item)).or_default().push(item);
+        }
+        groups
+    }
+
+    // 每组从 init() 开始，依次用 fold 把元素累加进去
+    pub fn fold_by<T, K, A, I, F, G, H>(items: I, mut key: F, mut init: G, mut fold: H) -> HashMap<K, A>
+        where I: IntoIterator<Item = T>, K: Eq + Hash, F: FnMut(&T) -> K, G: FnMut() -> A, H: FnMut(&mut A, T)
+    {
+        let mut folded = HashMap::new();
+        for item in items {
+            let acc = folded.entry(key(&item)).or_insert_with(&mut init);
+            fold(acc, item);
+        }
+        folded
+    }
+
+    // 值最大的 k 项，从大到小；值相同时按键从小到大，结果是确定的。
+    // 堆里只保留 k 项，复杂度 O(n log k)
+    pub fn top_k<K: Ord, V: Ord>(map: &HashMap<K, V>, k: usize) -> Vec<(&K, &V)> {
+        // 堆顶是目前 k 项里“最差”的一项
+        struct Ranked<'a, K, V>(&'a K, &'a V);
+
+        impl<K: Ord, V: Ord> Ord for Ranked<'_, K, V> {
+            fn cmp(&self, other: &Self) -> Ordering {
+                other.1.cmp(self.1).then_with(|| self.0.cmp(other.0))
+            }
+        }
+
+        impl<K: Ord, V: Ord> PartialOrd for Ranked<'_, K, V> {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl<K: Ord, V: Ord> PartialEq for Ranked<'_, K, V> {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl<K: Ord, V: Ord> Eq for Ranked<'_, K, V> {}
+
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (key, value) in map {
+            heap.push(Ranked(key, value));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+        // 按 Ranked 升序，也就是从最好到最差
+        heap.into_sorted_vec().into_iter().map(|Ranked(key, value)| (key, value)).collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const PANGRAM: &str = "the quick brown fox jumps over the lazy dog";
+
+        #[test]
+        fn count_by_counts_every_item_once() {
+            let words: Vec<&str> = PANGRAM.split_whitespace().collect();
+            let by_length = count_by(&words, |word| word.len());
+            assert_eq!((by_length[&3], by_length[&5], by_length[&4]), (4, 3, 2));
+            assert_eq!(by_length.values().sum::<usize>(), words.len());
+            assert!(count_by(Vec::<u32>::new(), |&x| x).is_empty());
+        }
+
+        // 每组里的元素保持原来的顺序
+        #[test]
+        fn group_by_keeps_the_original_order() {
+            let by_initial = group_by(PANGRAM.split_whitespace(), |word| word.chars().next());
+            assert_eq!(by_initial[&Some('t')], ["the", "the"]);
+            assert_eq!(by_initial[&Some('d')], ["dog"]);
+            let by_parity = group_by(1..=6, |n| n % 2);
+            assert_eq!((by_parity[&0].as_slice(), by_parity[&1].as_slice()), (&[2, 4, 6][..], &[1, 3, 5][..]));
+        }
+
+        // 每个首字母下的单词总长度
+        #[test]
+        fn fold_by_accumulates_each_group_from_init() {
+            let words: Vec<&str> = PANGRAM.split_whitespace().collect();
+            let letters_by_initial = fold_by(&words, |word| word.chars().next(), || 0, |total, word| *total += word.len());
+            assert_eq!((letters_by_initial[&Some('q')], letters_by_initial[&Some('t')]), (5, 6));
+            assert_eq!(letters_by_initial.values().sum::<usize>(), PANGRAM.len() - (words.len() - 1));
+        }
+
+        // o 出现 4 次，e 出现 3 次，h、r、t、u 各 2 次：同样多时按键从小到大
+        #[test]
+        fn top_k_breaks_ties_by_key() {
+            let letter_counts = count_by(PANGRAM.chars().filter(|c| c.is_alphabetic()), |&c| c);
+            let top: Vec<(char, usize)> = top_k(&letter_counts, 4).into_iter().map(|(&c, &n)| (c, n)).collect();
+            assert_eq!(top, [('o', 4), ('e', 3), ('h', 2), ('r', 2)]);
+            assert!(top_k(&letter_counts, 0).is_empty());
+            // k 比元素多时返回全部，仍然排好序
+            let all = top_k(&letter_counts, 100);
+            assert_eq!(all.len(), 26);
+            assert!(all.windows(2).all(|pair| (pair[1].1, pair[0].0) <= (pair[0].1, pair[1].0)));
+        }
+    }
+}
+
+mod tree {
+    use std::cell::{Ref, RefCell, RefMut};
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::rc::{Rc, Weak};
+
+    // 父节点用 Rc 拥有子节点，子节点用 Weak 指回父节点。
+    // 如果父指针也用 Rc，父子之间就成了引用环，计数永远到不了 0，整棵树都会泄漏；
+    // Weak 不算所有者，父节点被释放后 upgrade() 返回 None
+    pub struct Node<T> {
+        value: T,
+        parent: Weak<RefCell<Node<T>>>,
+        children: Vec<Rc<RefCell<Node<T>>>>,
+    }
+
+    // 指向一个节点的句柄，克隆它就是克隆里面的 Rc
+    pub struct NodeRef<T>(Rc<RefCell<Node<T>>>);
+
+    impl<T> Clone for NodeRef<T> {
+        fn clone(&self) -> Self {
+            NodeRef(Rc::clone(&self.0))
+        }
+    }
+
+    // 两个句柄指向同一个节点才相等
+    impl<T> PartialEq for NodeRef<T> {
+        fn eq(&self, other: &Self) -> bool {
+            Rc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    impl<T> NodeRef<T> {
+        pub fn new(value: T) -> Self {
+            NodeRef(Rc::new(RefCell::new(Node { value, parent: Weak::new(), children: Vec::new() })))
+        }
+
+        pub fn value(&self) -> Ref<'_, T> {
+            Ref::map(self.0.borrow(), |node| &node.value)
+        }
+
+        pub fn value_mut(&self) -> RefMut<'_, T> {
+            RefMut::map(self.0.borrow_mut(), |node| &mut node.value)
+        }
+
+        // 有几个所有者：父节点和所有的句柄
+        pub fn strong_count(&self) -> usize {
+            Rc::strong_count(&self.0)
+        }
+
+        // 有几个子节点指回它
+        pub fn weak_count(&self) -> usize {
+            Rc::weak_count(&self.0)
+        }
+
+        pub fn parent(&self) -> Option<NodeRef<T>> {
+            self.0.borrow().parent.upgrade().map(NodeRef)
+        }
+
+        pub fn children(&self) -> Vec<NodeRef<T>> {
+            self.0.borrow().children.iter().cloned().map(NodeRef).collect()
+        }
+
+        pub fn add_child(&self, value: T) -> NodeRef<T> {
+            let child = NodeRef::new(value);
+            self.append(&child);
+            child
+        }
+
+        // 把 child 连同它的子树挂到自己下面，原来有父节点的先摘下来。
+        // 不能把自己的祖先挂到自己下面，那样会成环
+        pub fn append(&self, child: &NodeRef<T>) {
+            assert!(!self.ancestors().any(|ancestor| ancestor == *child) && self != child,
+                    "cannot append a node to its own subtree");
+            child.detach();
+            child.0.borrow_mut().parent = Rc::downgrade(&self.0);
+            self.0.borrow_mut().children.push(Rc::clone(&child.0));
+        }
+
+        // 把自己（连同子树）从父节点上摘下来。返回 false 表示本来就没有父节点
+        pub fn detach(&self) -> bool {
+            let Some(parent) = self.parent() else { return false };
+            parent.0.borrow_mut().children.retain(|child| !Rc::ptr_eq(child, &self.0));
+            self.0.borrow_mut().parent = Weak::new();
+            true
+        }
+
+        // 删除自己，子节点按原来的顺序接到父节点上原来自己的位置
+        pub fn remove(&self) -> bool {
+            let Some(parent) = self.parent() else { return false };
+            let children = std::mem::take(&mut self.0.borrow_mut().children);
+            for child in &children {
+                child.borrow_mut().parent = Rc::downgrade(&parent.0);
+            }
+            let mut parent_node = parent.0.borrow_mut();
+            let index = parent_node.children.iter().position(|child| Rc::ptr_eq(child, &self.0)).unwrap();
+            parent_node.children.splice(index..=index, children);
+            drop(parent_node);
+            self.0.borrow_mut().parent = Weak::new();
+            true
+        }
+
+        // 从父节点一直往上到根
+        pub fn ancestors(&self) -> impl Iterator<Item = NodeRef<T>> {
+            std::iter::successors(self.parent(), NodeRef::parent)
+        }
+
+        pub fn depth(&self) -> usize {
+            self.ancestors().count()
+        }
+
+        // 先序遍历（深度优先），包括自己
+        pub fn preorder(&self) -> Vec<NodeRef<T>> {
+            let mut order = Vec::new();
+            let mut stack = vec![self.clone()];
+            while let Some(node) = stack.pop() {
+                stack.extend(node.children().into_iter().rev());
+                order.push(node);
+            }
+            order
+        }
+
+        // 层序遍历（广度优先），包括自己
+        pub fn breadth_first(&self) -> Vec<NodeRef<T>> {
+            let mut order = Vec::new();
+            let mut queue = VecDeque::from([self.clone()]);
+            while let Some(node) = queue.pop_front() {
+                queue.extend(node.children());
+                order.push(node);
+            }
+            order
+        }
+
+        pub fn find(&self, mut predicate: impl FnMut(&T) -> bool) -> Option<NodeRef<T>> {
+            self.preorder().into_iter().find(|node| predicate(&node.value()))
+        }
+    }
+
+    // 缩进显示整棵子树，每个节点后面是强引用和弱引用计数
+    impl<T: fmt::Display> fmt::Display for NodeRef<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let base = self.depth();
+            for node in self.preorder() {
+                // 遍历结果本身也持有一份强引用，显示时减掉
+                writeln!(f, "{:indent$}{} (strong {}, weak {})", "", node.value(),
+                         node.strong_count() - 1, node.weak_count(), indent = (node.depth() - base) * 2)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+mod leak_check {
+    use std::any;
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::fmt;
+    use std::hash::Hash;
+    use std::ops::Deref;
+    use std::rc::{Rc, Weak};
+
+    // 带登记的 Rc：每次分配、每个持有它的句柄都记到 Tracker 里。句柄分两种：
+    //      根句柄：局部变量之类“栈上”的所有者，TrackedRc::new 和 clone 得到的都是
+    //      边：某个被跟踪的值内部持有的句柄，用 owned_by 创建，记为 所有者 -> 被持有者 的一条边
+    // checkpoint 时在这张图上找强连通分量（环），从根句柄出发走不到的分配就是已经泄漏的。
+    // 普通的 Rc 做不到这一点，因为它不知道自己被谁持有
+    #[derive(Default)]
+    struct Registry {
+        next_id: usize,
+        // 活着的分配 -> 类型名
+        allocations: HashMap<usize, &'static str>,
+        roots: HashMap<usize, usize>,
+        edges: HashMap<(usize, usize), usize>,
+    }
+
+    #[derive(Clone, Default)]
+    pub struct Tracker {
+        registry: Rc<RefCell<Registry>>,
+    }
+
+    struct Inner<T> {
+        id: usize,
+        registry: Rc<RefCell<Registry>>,
+        value: T,
+    }
+
+    impl<T> Drop for Inner<T> {
+        fn drop(&mut self) {
+            self.registry.borrow_mut().allocations.remove(&self.id);
+        }
+    }
+
+    pub struct TrackedRc<T> {
+        inner: Rc<Inner<T>>,
+        // None 表示根句柄
+        owner: Option<usize>,
+    }
+
+    pub struct TrackedWeak<T>(Weak<Inner<T>>);
+
+    fn add<K: Eq + Hash>(counts: &mut HashMap<K, usize>, key: K) {
+        *counts.entry(key).or_insert(0) += 1;
+    }
+
+    fn release<K: Eq + Hash>(counts: &mut HashMap<K, usize>, key: K) {
+        let count = counts.get_mut(&key).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+
+    impl<T> TrackedRc<T> {
+        pub fn new(tracker: &Tracker, value: T) -> TrackedRc<T> {
+            let mut registry = tracker.registry.borrow_mut();
+            let id = registry.next_id;
+            registry.next_id += 1;
+            registry.allocations.insert(id, any::type_name::<T>());
+            add(&mut registry.roots, id);
+            let inner = Rc::new(Inner { id, registry: Rc::clone(&tracker.registry), value });
+            TrackedRc { inner, owner: None }
+        }
+
+        pub fn id(&self) -> usize {
+            self.inner.id
+        }
+
+        // 得到一个由 owner 持有的句柄，要存进 owner 的值里面
+        pub fn owned_by<U>(&self, owner: &TrackedRc<U>) -> TrackedRc<T> {
+            add(&mut self.inner.registry.borrow_mut().edges, (owner.id(), self.id()));
+            TrackedRc { inner: Rc::clone(&self.inner), owner: Some(owner.id()) }
+        }
+
+        // 把根句柄变成由 owner 持有的句柄
+        pub fn into_owned_by<U>(self, owner: &TrackedRc<U>) -> TrackedRc<T> {
+            self.owned_by(owner)
+        }
+
+        pub fn downgrade(&self) -> TrackedWeak<T> {
+            TrackedWeak(Rc::downgrade(&self.inner))
+        }
+
+        pub fn strong_count(&self) -> usize {
+            Rc::strong_count(&self.inner)
+        }
+
+        pub fn ptr_eq(&self, other: &TrackedRc<T>) -> bool {
+            Rc::ptr_eq(&self.inner, &other.inner)
+        }
+    }
+
+    impl<T> TrackedWeak<T> {
+        // 升级得到的是根句柄
+        pub fn upgrade(&self) -> Option<TrackedRc<T>> {
+            let inner = self.0.upgrade()?;
+            add(&mut inner.registry.borrow_mut().roots, inner.id);
+            Some(TrackedRc { inner, owner: None })
+        }
+    }
+
+    // 克隆出来的是根句柄，不管原来的句柄是不是边
+    impl<T> Clone for TrackedRc<T> {
+        fn clone(&self) -> Self {
+            add(&mut self.inner.registry.borrow_mut().roots, self.id());
+            TrackedRc { inner: Rc::clone(&self.inner), owner: None }
+        }
+    }
+
+    impl<T> Drop for TrackedRc<T> {
+        fn drop(&mut self) {
+            let mut registry = self.inner.registry.borrow_mut();
+            match self.owner {
+                None => release(&mut registry.roots, self.id()),
+                Some(owner) => release(&mut registry.edges, (owner, self.id())),
+            }
+            // 函数返回之后才释放 inner，那时 registry 已经不再被借用
+        }
+    }
+
+    impl<T> Deref for TrackedRc<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.inner.value
+        }
+    }
+
+    // 去掉类型名里的模块路径：alloc::vec::Vec<my_crate::Node> -> Vec<Node>
+    fn short_type_name(name: &str) -> String {
+        let mut short = String::new();
+        let mut segment = String::new();
+        let mut chars = name.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == ':' && chars.peek() == Some(&':') {
+                chars.next();
+                segment.clear();
+            } else if c.is_alphanumeric() || c == '_' {
+                segment.push(c);
+            } else {
+                short.push_str(&segment);
+                segment.clear();
+                short.push(c);
+            }
+        }
+        short + &segment
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Cycle {
+        // 环上的分配，按 id 排序
+        pub members: Vec<(usize, String)>,
+        // 从根句柄已经走不到这个环，它永远不会被释放了
+        pub leaked: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct LeakReport {
+        pub live: usize,
+        pub cycles: Vec<Cycle>,
+        // 所有走不到的分配：环本身以及只被环持有的分配
+        pub leaked: Vec<(usize, String)>,
+        // 所有者已经释放、句柄却还活着的边（所有者 id，被持有者 id），
+        // 通常是 owned_by 得到的句柄被挪到了别处。这种句柄按根句柄算
+        pub orphaned_edges: Vec<(usize, usize)>,
+    }
+
+    impl LeakReport {
+        pub fn is_clean(&self) -> bool {
+            self.leaked.is_empty()
+        }
+
+        // 泄漏的类型及个数
+        pub fn leaked_types(&self) -> BTreeMap<&str, usize> {
+            let mut types = BTreeMap::new();
+            for (_, name) in &self.leaked {
+                *types.entry(name.as_str()).or_insert(0) += 1;
+            }
+            types
+        }
+    }
+
+    impl fmt::Display for LeakReport {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "{} live allocations, {} cycles, {} leaked", self.live, self.cycles.len(), self.leaked.len())?;
+            for cycle in &self.cycles {
+                let members: Vec<String> = cycle.members.iter().map(|(id, name)| format!("{}#{}", name, id)).collect();
+                writeln!(f, "  {} cycle: {}", if cycle.leaked { "leaked" } else { "reachable" }, members.join(", "))?;
+            }
+            for (name, count) in self.leaked_types() {
+                writeln!(f, "  leaked {} x {}", count, name)?;
+            }
+            for (owner, id) in &self.orphaned_edges {
+                writeln!(f, "  #{} is held by a handle whose owner #{} is gone", id, owner)?;
+            }
+            Ok(())
+        }
+    }
+
+    // Tarjan 强连通分量算法。用显式的栈代替递归，很长的链也不会栈溢出
+    struct Tarjan<'a> {
+        graph: &'a BTreeMap<usize, Vec<usize>>,
+        index: HashMap<usize, usize>,
+        low: HashMap<usize, usize>,
+        stack: Vec<usize>,
+        on_stack: BTreeSet<usize>,
+        components: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn enter(&mut self, node: usize) {
+            let index = self.index.len();
+            self.index.insert(node, index);
+            self.low.insert(node, index);
+            self.stack.push(node);
+            self.on_stack.insert(node);
+        }
+
+        fn visit(&mut self, root: usize) {
+            self.enter(root);
+            // 每一帧是（节点，下一个要看的邻居的下标），相当于递归版本的调用栈
+            let mut calls = vec![(root, 0)];
+            while let Some(frame) = calls.last_mut() {
+                let (node, child) = *frame;
+                match self.graph.get(&node).and_then(|targets| targets.get(child)) {
+                    Some(&next) => {
+                        frame.1 += 1;
+                        if !self.index.contains_key(&next) {
+                            self.enter(next);
+                            calls.push((next, 0));
+                        } else if self.on_stack.contains(&next) {
+                            let low = self.low[&node].min(self.index[&next]);
+                            self.low.insert(node, low);
+                        }
+                    }
+                    None => {
+                        calls.pop();
+                        if let Some(&(parent, _)) = calls.last() {
+                            let low = self.low[&parent].min(self.low[&node]);
+                            self.low.insert(parent, low);
+                        }
+                        if self.low[&node] == self.index[&node] {
+                            self.pop_component(node);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn pop_component(&mut self, node: usize) {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(&member);
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            component.sort();
+            self.components.push(component);
+        }
+    }
+
+    impl Tracker {
+        pub fn new() -> Tracker {
+            Tracker::default()
+        }
+
+        pub fn live(&self) -> usize {
+            self.registry.borrow().allocations.len()
+        }
+
+        pub fn checkpoint(&self) -> LeakReport {
+            let registry = self.registry.borrow();
+            let mut graph: BTreeMap<usize, Vec<usize>> = registry.allocations.keys().map(|&id| (id, Vec::new())).collect();
+            let mut orphaned_edges = Vec::new();
+            for &(from, to) in registry.edges.keys() {
+                match graph.get_mut(&from) {
+                    Some(targets) => targets.push(to),
+                    None => orphaned_edges.push((from, to)),
+                }
+            }
+            graph.values_mut().for_each(|targets| targets.sort());
+            orphaned_edges.sort();
+
+            // 从所有根句柄（包括所有者已经不在的句柄）出发能走到的分配
+            let mut reachable: BTreeSet<usize> = BTreeSet::new();
+            let mut pending: Vec<usize> = registry.roots.keys().copied().collect();
+            pending.extend(orphaned_edges.iter().map(|&(_, to)| to));
+            while let Some(id) = pending.pop() {
+                if reachable.insert(id) {
+                    pending.extend(&graph[&id]);
+                }
+            }
+
+            let mut tarjan = Tarjan {
+                graph: &graph,
+                index: HashMap::new(),
+                low: HashMap::new(),
+                stack: Vec::new(),
+                on_stack: BTreeSet::new(),
+                components: Vec::new(),
+            };
+            for &id in graph.keys() {
+                if !tarjan.index.contains_key(&id) {
+                    tarjan.visit(id);
+                }
+            }
+            let describe = |id: usize| (id, short_type_name(registry.allocations[&id]));
+            let mut cycles: Vec<Cycle> = tarjan.components.into_iter()
+                // 只有一个成员的分量，除非自己指向自己，否则不是环
+                .filter(|component| component.len() > 1 || graph[&component[0]].contains(&component[0]))
+                .map(|component| Cycle {
+                    leaked: !reachable.contains(&component[0]),
+                    members: component.into_iter().map(describe).collect(),
+                })
+                .collect();
+            cycles.sort_by_key(|cycle| cycle.members[0].0);
+            LeakReport {
+                live: registry.allocations.len(),
+                cycles,
+                leaked: graph.keys().filter(|id| !reachable.contains(id)).map(|&id| describe(id)).collect(),
+                orphaned_edges,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        struct Parent {
+            children: RefCell<Vec<TrackedRc<Child>>>,
+        }
+
+        struct Child {
+            parent: RefCell<Option<TrackedRc<Parent>>>,
+            // 只被子节点持有的数据，子节点泄漏了它也跟着泄漏
+            payload: RefCell<Option<TrackedRc<String>>>,
+        }
+
+        // 错误的写法：子节点用强引用指回父节点
+        fn family(tracker: &Tracker) -> TrackedRc<Parent> {
+            let parent = TrackedRc::new(tracker, Parent { children: RefCell::new(Vec::new()) });
+            for name in ["Alice", "Bob"] {
+                let child = TrackedRc::new(tracker, Child { parent: RefCell::new(None), payload: RefCell::new(None) });
+                *child.payload.borrow_mut() = Some(TrackedRc::new(tracker, name.to_string()).into_owned_by(&child));
+                *child.parent.borrow_mut() = Some(parent.owned_by(&child));
+                parent.children.borrow_mut().push(child.into_owned_by(&parent));
+            }
+            parent
+        }
+
+        // 还有根句柄，环是可达的，不算泄漏；把子节点指回父节点的引用拿掉，环就断了
+        #[test]
+        fn reachable_cycle_is_reported_and_can_be_broken() {
+            let tracker = Tracker::new();
+            let parent = family(&tracker);
+            let report = tracker.checkpoint();
+            assert_eq!((report.live, report.cycles.len(), report.is_clean()), (5, 1, true));
+            assert_eq!(report.cycles[0].members.iter().map(|(_, name)| name.as_str()).collect::<Vec<_>>(), ["Parent", "Child", "Child"]);
+            assert!(!report.cycles[0].leaked);
+            for child in parent.children.borrow().iter() {
+                child.parent.borrow_mut().take();
+            }
+            assert!(tracker.checkpoint().cycles.is_empty());
+            assert_eq!(parent.strong_count(), 1);
+            drop(parent);
+            assert_eq!(tracker.live(), 0);
+        }
+
+        // 最后一个根句柄放掉时环还在，环上的分配和只被环持有的分配都泄漏了
+        #[test]
+        fn dropping_the_last_handle_leaks_the_cycle() {
+            let tracker = Tracker::new();
+            let parent = family(&tracker);
+            let rescue: TrackedWeak<Parent> = parent.downgrade();
+            drop(parent);
+            let report = tracker.checkpoint();
+            assert!(!report.is_clean() && report.cycles[0].leaked);
+            assert_eq!(report.leaked_types().into_iter().collect::<Vec<_>>(), [("Child", 2), ("Parent", 1), ("String", 2)]);
+            // 留了一个 Weak 作后门，才能把环拆掉
+            let parent = rescue.upgrade().unwrap();
+            parent.children.borrow_mut().clear();
+            drop(parent);
+            assert_eq!(tracker.live(), 0);
+            assert!(rescue.upgrade().is_none());
+        }
+
+        #[test]
+        fn a_node_holding_itself_is_a_cycle() {
+            struct Selfish {
+                me: RefCell<Option<TrackedRc<Selfish>>>,
+            }
+            let tracker = Tracker::new();
+            let selfish = TrackedRc::new(&tracker, Selfish { me: RefCell::new(None) });
+            *selfish.me.borrow_mut() = Some(selfish.owned_by(&selfish));
+            let selfish_id = selfish.id();
+            let escape = selfish.downgrade();
+            drop(selfish);
+            let report = tracker.checkpoint();
+            assert_eq!(report.cycles.len(), 1);
+            assert_eq!(report.leaked, [(selfish_id, "Selfish".to_string())]);
+            let selfish = escape.upgrade().unwrap();
+            assert!(selfish.me.borrow().as_ref().unwrap().ptr_eq(&selfish));
+            selfish.me.borrow_mut().take();
+            drop(selfish);
+            assert_eq!(tracker.checkpoint(), LeakReport { live: 0, cycles: Vec::new(), leaked: Vec::new(), orphaned_edges: Vec::new() });
+        }
+
+        // owned_by 得到的句柄被挪出了所有者，所有者先释放了。这条边要报告出来，句柄按根句柄算
+        #[test]
+        fn a_handle_that_outlives_its_owner_is_an_orphaned_edge() {
+            let tracker = Tracker::new();
+            let owner = TrackedRc::new(&tracker, Parent { children: RefCell::new(Vec::new()) });
+            let value = TrackedRc::new(&tracker, "kept".to_string());
+            let kept = value.owned_by(&owner);
+            let owner_id = owner.id();
+            drop((owner, value));
+            let report = tracker.checkpoint();
+            assert_eq!(report.orphaned_edges, [(owner_id, kept.id())]);
+            assert!(report.is_clean());
+            drop(kept);
+            assert_eq!(tracker.live(), 0);
+        }
+
+        // 很长的环：checkpoint 不能递归得太深
+        #[test]
+        fn a_ring_of_100001_nodes() {
+            struct Link {
+                next: RefCell<Option<TrackedRc<Link>>>,
+            }
+            let tracker = Tracker::new();
+            let head = TrackedRc::new(&tracker, Link { next: RefCell::new(None) });
+            let mut tail = head.clone();
+            for _ in 0..100_000 {
+                let link = TrackedRc::new(&tracker, Link { next: RefCell::new(None) });
+                *tail.next.borrow_mut() = Some(link.owned_by(&tail));
+                tail = link;
+            }
+            *tail.next.borrow_mut() = Some(head.owned_by(&tail));
+            drop(tail);
+            let report = tracker.checkpoint();
+            assert_eq!((report.cycles.len(), report.cycles[0].members.len(), report.is_clean()), (1, 100_001, true));
+            // 逐个拆开，一次性释放整条链的话 drop 本身也会递归得很深
+            let mut link = head.next.borrow_mut().take();
+            while let Some(current) = link {
+                link = current.next.borrow_mut().take();
+            }
+            drop(head);
+            assert_eq!(tracker.live(), 0);
+        }
+    }
+}